@@ -32,6 +32,11 @@ if [ -z "${CHANNEL}" ]; then
   exit 1
 fi
 
+if [ "${DRY_RUN}" = false ] && [ -z "${LIBSNOW_SIGNING_KEY_FILE:-}" ]; then
+  echo "LIBSNOW_SIGNING_KEY_FILE must point to the database signing key" >&2
+  exit 1
+fi
+
 mkdir -p "${OUTPUT}"
 
 TMPDIR=$(mktemp -d)
@@ -102,7 +107,7 @@ FAILED=0
 for (( i=${#PENDING[@]}-1; i>=0; i-- )); do
   IFS='|' read -r RELEASE GIT_REV <<< "${PENDING[$i]}"
 
-  if generate-db --channel "${CHANNEL}" --release "${RELEASE}" --output "${OUTPUT}" --verbose; then
+  if generate-db --channel "${CHANNEL}" --release "${RELEASE}" --output "${OUTPUT}" --verbose --signing-key "${LIBSNOW_SIGNING_KEY_FILE}"; then
     DB_FILE="${OUTPUT}/${GIT_REV}.db"
     if [ ! -f "${DB_FILE}" ]; then
       echo "ERROR: expected ${DB_FILE} not found" >&2
@@ -121,8 +126,14 @@ for (( i=${#PENDING[@]}-1; i>=0; i-- )); do
         --key "db/${GIT_REV}" \
         --body "${BR_FILE}" \
         --content-encoding br
+      for EXT in sha256 sig; do
+        aws s3api put-object \
+          --bucket "${BUCKET}" \
+          --key "db/${GIT_REV}.${EXT}" \
+          --body "${DB_FILE}.${EXT}"
+      done
       GENERATED=$((GENERATED + 1))
-      rm -f "${BR_FILE}"
+      rm -f "${BR_FILE}" "${DB_FILE}.sha256" "${DB_FILE}.sig"
     fi
   else
     echo "FAILED ${CHANNEL}/${RELEASE}" >&2
@@ -1,8 +1,11 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path};
 
 use anyhow::{Context, Result};
 use clap::Parser;
-use libsnow::metadata::{build_search_index_in_dir, index_dir_for_db_path};
+use libsnow::metadata::{
+    MANIFEST_EXTENSION, SIGNATURE_EXTENSION, build_search_index_in_dir, checksum_manifest,
+    index_dir_for_db_path, is_commit, sign_manifest,
+};
 use regex::Regex;
 use rusqlite::Connection;
 use serde::Deserialize;
@@ -10,14 +13,16 @@ use serde_json::Value;
 use tracing::info;
 
 #[derive(Parser, Debug)]
-#[command(about = "Generate the libsnow SQLite package database from nixos releases")]
+#[command(
+    about = "Generate the libsnow SQLite package database from nixos releases or a local nixpkgs"
+)]
 struct Args {
     /// Channel path, e.g. "nixos/unstable/nixos-24.11pre123456.abcdef0"
     /// or just a channel prefix like "nixpkgs" / "nixos/unstable".
     /// When a full release name is given it is used directly.
     /// When only a channel prefix is given the latest release is resolved automatically.
-    #[arg(short, long)]
-    channel: String,
+    #[arg(short, long, required_unless_present = "nixpkgs")]
+    channel: Option<String>,
 
     /// Specific release name inside the channel (e.g. "nixos-24.11pre123456.abcdef0").
     /// If omitted the latest release is fetched from the S3 bucket listing.
@@ -35,6 +40,37 @@ struct Args {
     /// Also generate a persisted Tantivy search index next to the .db file
     #[arg(long)]
     with_index: bool,
+
+    /// Ed25519 secret key file (`name:base64`, as produced by
+    /// `nix-store --generate-binary-cache-key`) used to sign the SHA-256 manifest.
+    /// When omitted only the unsigned manifest is written.
+    #[arg(long)]
+    signing_key: Option<String>,
+
+    /// `programs.sqlite` from a NixOS channel, used to record every binary
+    /// a package provides rather than only its `meta.mainProgram`
+    #[arg(long)]
+    programs_sqlite: Option<String>,
+
+    /// Build from a local nixpkgs checkout or store path instead of a
+    /// release, evaluating it with `nix-env` and `nix-build`
+    #[arg(long, conflicts_with_all = ["channel", "release"])]
+    nixpkgs: Option<String>,
+
+    /// Revision used to name the database in `--nixpkgs` mode. Defaults to
+    /// the tree's `.git-revision` file or its git HEAD.
+    #[arg(long, requires = "nixpkgs")]
+    revision: Option<String>,
+
+    /// Leave out NixOS program and module options in `--nixpkgs` mode
+    /// instead of failing when `nixos/release.nix` cannot build them
+    #[arg(long, requires = "nixpkgs")]
+    skip_options: bool,
+
+    /// home-manager `options.json` to read instead of building it from
+    /// GitHub. Without it, `--nixpkgs` mode leaves out home-manager options.
+    #[arg(long)]
+    hm_options: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -87,6 +123,50 @@ struct S3Content {
     pub last_modified: String,
 }
 
+/// A command provided by a package.
+struct Binary {
+    name: String,
+    attribute: String,
+    /// Whether this is the package's `meta.mainProgram`.
+    main_program: bool,
+}
+
+fn collect_binaries(
+    packages: &HashMap<String, Package>,
+    programs_sqlite: Option<&str>,
+) -> Result<Vec<Binary>> {
+    let mut binaries: Vec<Binary> = packages
+        .iter()
+        .filter_map(|(attr, pkg)| {
+            let name = pkg.meta.as_ref()?.main_program.as_deref()?;
+            (!name.is_empty()).then(|| Binary {
+                name: name.to_string(),
+                attribute: attr.clone(),
+                main_program: true,
+            })
+        })
+        .collect();
+
+    if let Some(path) = programs_sqlite {
+        let conn = Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to open programs listing {}", path))?;
+        let mut stmt = conn.prepare("SELECT DISTINCT name, package FROM Programs")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in rows {
+            let (name, attribute): (String, String) = row?;
+            if packages.contains_key(&attribute) {
+                binaries.push(Binary {
+                    name,
+                    attribute,
+                    main_program: false,
+                });
+            }
+        }
+    }
+
+    Ok(binaries)
+}
+
 async fn resolve_latest_release(channel: &str) -> Result<String> {
     let url = format!(
         "https://nix-releases.s3.amazonaws.com/?delimiter=/&prefix={}/",
@@ -146,10 +226,7 @@ async fn fetch_packages(channel: &str, release: &str) -> Result<HashMap<String,
     Ok(pkg_json.packages)
 }
 
-async fn fetch_program_options(
-    channel: &str,
-    release: &str,
-) -> Result<HashMap<String, HashMap<String, Value>>> {
+async fn fetch_nixos_options(channel: &str, release: &str) -> Result<HashMap<String, Value>> {
     let url = format!(
         "https://releases.nixos.org/{}/{}/options.json.br",
         channel, release
@@ -167,14 +244,12 @@ async fn fetch_program_options(
 
     let bytes = resp.bytes().await?;
     let all_options: HashMap<String, Value> = serde_json::from_slice(&bytes)?;
-    let programs = extract_program_options(all_options);
-
-    info!("Found {} programs with NixOS options", programs.len());
-    Ok(programs)
+    info!("Got {} NixOS options", all_options.len());
+    Ok(all_options)
 }
 
 fn extract_program_options(
-    all_options: HashMap<String, Value>,
+    all_options: &HashMap<String, Value>,
 ) -> HashMap<String, HashMap<String, Value>> {
     let mut programs: HashMap<String, HashMap<String, Value>> = HashMap::new();
     for (key, value) in all_options {
@@ -184,13 +259,88 @@ fn extract_program_options(
             programs
                 .entry(prog_name.to_string())
                 .or_default()
-                .insert(key, value);
+                .insert(key.clone(), value.clone());
         }
     }
     programs.retain(|name, opts| opts.contains_key(&format!("programs.{}.enable", name)));
     programs
 }
 
+/// Option namespaces searched for enable-able modules besides `programs`.
+const MODULE_NAMESPACES: &[&str] = &[
+    "services.",
+    "virtualisation.",
+    "hardware.",
+    "security.",
+    "networking.",
+];
+
+/// A NixOS module with a boolean `enable` option, e.g. `services.tailscale`.
+struct Module {
+    /// The package the module installs, if it can be told.
+    package: Option<String>,
+    /// Options of this module, excluding those of modules nested inside it.
+    options: HashMap<String, Value>,
+}
+
+fn extract_modules(
+    all_options: &HashMap<String, Value>,
+    packages: &HashMap<String, Package>,
+) -> HashMap<String, Module> {
+    let in_namespace = |key: &str| MODULE_NAMESPACES.iter().any(|ns| key.starts_with(ns));
+
+    let mut modules: HashMap<String, Module> = all_options
+        .iter()
+        .filter(|(key, opt)| {
+            in_namespace(key)
+                && !key.contains(['<', '*'])
+                && opt.get("type").and_then(Value::as_str) == Some("boolean")
+        })
+        .filter_map(|(key, _)| key.strip_suffix(".enable"))
+        .map(|path| {
+            (
+                path.to_string(),
+                Module {
+                    package: None,
+                    options: HashMap::new(),
+                },
+            )
+        })
+        .collect();
+
+    // Give every option to the innermost module it belongs to
+    for (key, value) in all_options.iter().filter(|(k, _)| in_namespace(k)) {
+        let owner = key
+            .match_indices('.')
+            .map(|(i, _)| &key[..i])
+            .rev()
+            .find(|prefix| modules.contains_key(*prefix));
+        if let Some(owner) = owner
+            && let Some(module) = modules.get_mut(owner)
+        {
+            module.options.insert(key.clone(), value.clone());
+        }
+    }
+
+    for (path, module) in modules.iter_mut() {
+        let from_package_option = module
+            .options
+            .get(&format!("{}.package", path))
+            .and_then(|opt| opt.get("default"))
+            .and_then(|default| default.get("text"))
+            .and_then(Value::as_str)
+            .and_then(|text| text.trim().strip_prefix("pkgs."))
+            .map(str::to_string);
+        let from_name = path.rsplit('.').next().map(str::to_string);
+        module.package = [from_package_option, from_name]
+            .into_iter()
+            .flatten()
+            .find(|attr| packages.contains_key(attr));
+    }
+
+    modules
+}
+
 fn fetch_hm_program_options(channel: &str) -> Result<HashMap<String, HashMap<String, Value>>> {
     let hm_branch = if channel.contains("unstable") || channel.starts_with("nixpkgs") {
         "master".to_string()
@@ -222,14 +372,19 @@ fn fetch_hm_program_options(channel: &str) -> Result<HashMap<String, HashMap<Str
     }
 
     let store_path = String::from_utf8(output.stdout)?.trim().to_string();
-    let json_path = format!("{}/share/doc/home-manager/options.json", store_path);
+    read_hm_program_options(&format!(
+        "{}/share/doc/home-manager/options.json",
+        store_path
+    ))
+}
 
+fn read_hm_program_options(json_path: &str) -> Result<HashMap<String, HashMap<String, Value>>> {
     info!("Reading HM options from {}", json_path);
-    let content = std::fs::read_to_string(&json_path)
+    let content = std::fs::read_to_string(json_path)
         .with_context(|| format!("Failed to read {}", json_path))?;
     let all_options: HashMap<String, Value> = serde_json::from_str(&content)?;
 
-    let programs = extract_program_options(all_options);
+    let programs = extract_program_options(&all_options);
     info!(
         "Found {} programs with home-manager options",
         programs.len()
@@ -337,6 +492,177 @@ fn parse_aliases(source: &str) -> Vec<ParsedAlias> {
     aliases
 }
 
+fn read_aliases(nixpkgs: &Path) -> Result<Vec<ParsedAlias>> {
+    let path = nixpkgs.join("pkgs/top-level/aliases.nix");
+    info!("Reading aliases from {}", path.display());
+    let body = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let aliases = parse_aliases(&body);
+
+    info!("Parsed {} aliases", aliases.len());
+    Ok(aliases)
+}
+
+/// Revision of a local nixpkgs tree: `.git-revision` in tarballs and store
+/// paths, otherwise the HEAD of a git checkout.
+fn local_git_revision(nixpkgs: &Path) -> Result<String> {
+    if let Ok(rev) = std::fs::read_to_string(nixpkgs.join(".git-revision"))
+        && !rev.trim().is_empty()
+    {
+        return Ok(rev.trim().to_string());
+    }
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(nixpkgs)
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .context("Failed to run git rev-parse")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Cannot determine the revision of {}, pass --revision",
+            nixpkgs.display()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+/// Evaluate package metadata the way nixpkgs generates `packages.json`.
+fn eval_packages(nixpkgs: &Path) -> Result<HashMap<String, Package>> {
+    let packages_config = nixpkgs.join("pkgs/top-level/packages-config.nix");
+    let config = if packages_config.exists() {
+        format!("import {}", packages_config.display())
+    } else {
+        "{ allowAliases = false; allowUnfree = true; }".to_string()
+    };
+
+    info!("Evaluating packages in {}", nixpkgs.display());
+    let output = std::process::Command::new("nix-env")
+        .arg("-f")
+        .arg(nixpkgs)
+        .arg("-I")
+        .arg(format!("nixpkgs={}", nixpkgs.display()))
+        .args(["-qa", "--meta", "--json", "--arg", "config", &config])
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .context("Failed to run nix-env")?;
+    if !output.status.success() {
+        anyhow::bail!("nix-env failed to evaluate {}", nixpkgs.display());
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Build the NixOS manual's `options.json` from `nixos/release.nix`.
+fn build_nixos_options(nixpkgs: &Path) -> Result<HashMap<String, Value>> {
+    info!("Building NixOS options.json from {}", nixpkgs.display());
+    let output = std::process::Command::new("nix-build")
+        .arg(nixpkgs.join("nixos/release.nix"))
+        .args(["-A", "options", "--no-out-link"])
+        .output()
+        .context("Failed to run nix-build")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!(
+            "NixOS options build failed (pass --skip-options to leave them out): {}",
+            stderr.trim()
+        );
+    }
+
+    let store_path = String::from_utf8(output.stdout)?.trim().to_string();
+    let json_path = format!("{}/share/doc/nixos/options.json", store_path);
+    let content = std::fs::read_to_string(&json_path)
+        .with_context(|| format!("Failed to read {}", json_path))?;
+    let all_options: HashMap<String, Value> = serde_json::from_str(&content)?;
+    info!("Got {} NixOS options", all_options.len());
+    Ok(all_options)
+}
+
+/// Everything a database is built from.
+struct Sources {
+    git_rev: String,
+    packages: HashMap<String, Package>,
+    nixos_options: HashMap<String, Value>,
+    hm_program_options: HashMap<String, HashMap<String, Value>>,
+    aliases: Vec<ParsedAlias>,
+}
+
+async fn fetch_release_sources(args: &Args, channel: &str) -> Result<Sources> {
+    // Resolve the release name
+    let release = match &args.release {
+        Some(r) => r.clone(),
+        None => {
+            info!(
+                "No release specified, resolving latest for channel '{}'",
+                channel
+            );
+            let r = resolve_latest_release(channel).await?;
+            info!("Resolved latest release: {}", r);
+            r
+        }
+    };
+
+    // Fetch git revision (used as the db filename, matching the original generator)
+    let git_rev = fetch_git_revision(channel, &release).await?;
+    info!("Git revision: {}", git_rev);
+
+    // Fetch package metadata and program options
+    info!("Fetching package metadata for {}/{} ...", channel, release);
+    let packages = fetch_packages(channel, &release).await?;
+    info!("Got {} packages", packages.len());
+
+    let nixos_options = fetch_nixos_options(channel, &release).await?;
+    let hm_program_options = match &args.hm_options {
+        Some(path) => read_hm_program_options(path)?,
+        None => fetch_hm_program_options(channel)?,
+    };
+    let aliases = fetch_aliases(&git_rev).await?;
+
+    Ok(Sources {
+        git_rev,
+        packages,
+        nixos_options,
+        hm_program_options,
+        aliases,
+    })
+}
+
+fn read_local_sources(args: &Args, nixpkgs: &Path) -> Result<Sources> {
+    let git_rev = match &args.revision {
+        Some(rev) => rev.clone(),
+        None => local_git_revision(nixpkgs)?,
+    };
+    if !is_commit(&git_rev) {
+        anyhow::bail!("Revision {:?} is not a 40-character commit hash", git_rev);
+    }
+    info!("Git revision: {}", git_rev);
+
+    let packages = eval_packages(nixpkgs)?;
+    info!("Got {} packages", packages.len());
+
+    let nixos_options = if args.skip_options {
+        info!("--skip-options given, skipping NixOS options");
+        HashMap::new()
+    } else {
+        build_nixos_options(nixpkgs)?
+    };
+    let hm_program_options = match &args.hm_options {
+        Some(path) => read_hm_program_options(path)?,
+        None => {
+            info!("No --hm-options given, skipping home-manager options");
+            HashMap::new()
+        }
+    };
+    let aliases = read_aliases(nixpkgs)?;
+
+    Ok(Sources {
+        git_rev,
+        packages,
+        nixos_options,
+        hm_program_options,
+        aliases,
+    })
+}
+
 async fn fetch_git_revision(channel: &str, release: &str) -> Result<String> {
     let url = format!(
         "https://releases.nixos.org/{}/{}/git-revision",
@@ -350,6 +676,8 @@ fn create_database(
     packages: &HashMap<String, Package>,
     program_options: &HashMap<String, HashMap<String, Value>>,
     hm_program_options: &HashMap<String, HashMap<String, Value>>,
+    modules: &HashMap<String, Module>,
+    binaries: &[Binary],
     aliases: &[ParsedAlias],
     db_path: &str,
 ) -> Result<()> {
@@ -413,6 +741,27 @@ fn create_database(
         [],
     )?;
 
+    conn.execute(
+        r#"CREATE TABLE modules (
+            "path" TEXT NOT NULL UNIQUE,
+            "attribute" TEXT,
+            "options" JSON NOT NULL,
+            PRIMARY KEY("path")
+        )"#,
+        [],
+    )?;
+
+    conn.execute(
+        r#"CREATE TABLE binaries (
+            "name" TEXT NOT NULL,
+            "attribute" TEXT NOT NULL,
+            "main_program" INTEGER NOT NULL,
+            PRIMARY KEY("name", "attribute"),
+            FOREIGN KEY("attribute") REFERENCES "pkgs" ("attribute")
+        )"#,
+        [],
+    )?;
+
     conn.execute(
         r#"CREATE TABLE aliases (
             "alias" TEXT NOT NULL UNIQUE,
@@ -434,6 +783,14 @@ fn create_database(
         r#"CREATE INDEX "idx_hm_program_options" ON "hm_program_options" ("attribute")"#,
         [],
     )?;
+    conn.execute(
+        r#"CREATE INDEX "idx_modules_attribute" ON "modules" ("attribute")"#,
+        [],
+    )?;
+    conn.execute(
+        r#"CREATE INDEX "idx_binaries_attribute" ON "binaries" ("attribute")"#,
+        [],
+    )?;
     conn.execute(r#"CREATE INDEX "idx_aliases" ON "aliases" ("alias")"#, [])?;
 
     // Insert in a single transaction for speed
@@ -515,6 +872,24 @@ fn create_database(
             let json = serde_json::to_string(opts)?;
             hm_stmt.execute(rusqlite::params![prog, json])?;
         }
+
+        let mut module_stmt = conn.prepare(
+            "INSERT OR IGNORE INTO modules (path, attribute, options) VALUES (?1, ?2, ?3)",
+        )?;
+        for (path, module) in modules {
+            let json = serde_json::to_string(&module.options)?;
+            module_stmt.execute(rusqlite::params![path, module.package, json])?;
+        }
+    }
+
+    {
+        // mainProgram entries come first, so they win over the listing
+        let mut bin_stmt = conn.prepare(
+            "INSERT OR IGNORE INTO binaries (name, attribute, main_program) VALUES (?1, ?2, ?3)",
+        )?;
+        for b in binaries {
+            bin_stmt.execute(rusqlite::params![b.name, b.attribute, b.main_program])?;
+        }
     }
 
     {
@@ -537,6 +912,29 @@ fn create_database(
     Ok(())
 }
 
+/// Write `<db>.sha256` and, when a key is given, the detached `<db>.sig`.
+fn write_manifest(db_path: &str, signing_key: Option<&str>) -> Result<()> {
+    let bytes = std::fs::read(db_path)?;
+    let file_name = std::path::Path::new(db_path)
+        .file_name()
+        .context("Invalid database path")?
+        .to_string_lossy();
+    let manifest = checksum_manifest(&bytes, &file_name);
+    let manifest_path = format!("{}.{}", db_path, MANIFEST_EXTENSION);
+    std::fs::write(&manifest_path, &manifest)?;
+    info!("Manifest written to {}", manifest_path);
+
+    if let Some(key_path) = signing_key {
+        let key = std::fs::read_to_string(key_path)
+            .with_context(|| format!("Failed to read signing key {}", key_path))?;
+        let signature = sign_manifest(&manifest, &key)?;
+        let signature_path = format!("{}.{}", db_path, SIGNATURE_EXTENSION);
+        std::fs::write(&signature_path, signature)?;
+        info!("Signature written to {}", signature_path);
+    }
+    Ok(())
+}
+
 fn create_search_index(db_path: &str) -> Result<()> {
     let conn = Connection::open(db_path)?;
     let index_dir = index_dir_for_db_path(std::path::Path::new(db_path));
@@ -559,34 +957,27 @@ async fn main() -> Result<()> {
         tracing_subscriber::fmt().with_env_filter(filter).init();
     }
 
-    let channel = &args.channel;
-
-    // Resolve the release name
-    let release = match args.release {
-        Some(r) => r,
-        None => {
-            info!(
-                "No release specified, resolving latest for channel '{}'",
-                channel
-            );
-            let r = resolve_latest_release(channel).await?;
-            info!("Resolved latest release: {}", r);
-            r
-        }
+    let Sources {
+        git_rev,
+        packages,
+        nixos_options,
+        hm_program_options,
+        aliases,
+    } = match (&args.nixpkgs, &args.channel) {
+        (Some(nixpkgs), _) => read_local_sources(&args, Path::new(nixpkgs))?,
+        (None, Some(channel)) => fetch_release_sources(&args, channel).await?,
+        (None, None) => unreachable!("clap requires --channel without --nixpkgs"),
     };
 
-    // Fetch git revision (used as the db filename, matching the original generator)
-    let git_rev = fetch_git_revision(channel, &release).await?;
-    info!("Git revision: {}", git_rev);
-
-    // Fetch package metadata and program options
-    info!("Fetching package metadata for {}/{} ...", channel, release);
-    let packages = fetch_packages(channel, &release).await?;
-    info!("Got {} packages", packages.len());
-
-    let program_options = fetch_program_options(channel, &release).await?;
-    let hm_program_options = fetch_hm_program_options(channel)?;
-    let aliases = fetch_aliases(&git_rev).await?;
+    let program_options = extract_program_options(&nixos_options);
+    info!(
+        "Found {} programs with NixOS options",
+        program_options.len()
+    );
+    let modules = extract_modules(&nixos_options, &packages);
+    info!("Found {} enable-able NixOS modules", modules.len());
+    let binaries = collect_binaries(&packages, args.programs_sqlite.as_deref())?;
+    info!("Found {} binaries", binaries.len());
 
     // Build the database
     let db_path = format!("{}/{}.db", args.output, git_rev);
@@ -594,9 +985,12 @@ async fn main() -> Result<()> {
         &packages,
         &program_options,
         &hm_program_options,
+        &modules,
+        &binaries,
         &aliases,
         &db_path,
     )?;
+    write_manifest(&db_path, args.signing_key.as_deref())?;
     if args.with_index {
         info!("Building search index ...");
         create_search_index(&db_path)?;
@@ -30,6 +30,11 @@ pub struct LibSnowConfig {
     /// Specifies which configuration should be used from the `nixosConfigurations` attribute set in the flake file.
     /// If not set, NixOS defaults to the hostname of the system.
     pub host: Option<String>,
+    /// Name of the nixpkgs input in the flake's `flake.lock`, used to find the
+    /// revision the next rebuild will use. Nested inputs are written as a path,
+    /// e.g. `home-manager/nixpkgs`. If not set, `nixpkgs` is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flake_nixpkgs_input: Option<String>,
     /// Specifies how many NixOS generations to keep. If set to 0, all generations will be kept.
     /// If not set, the default is 5.
     pub generations: Option<u32>,
@@ -39,6 +44,47 @@ pub struct LibSnowConfig {
     /// Whether home-manager is configured as part of the system config or seperately.
     #[serde(default)]
     pub system_for_home_manager: bool,
+    /// Ordered list of places to fetch package databases from. Each entry is either
+    /// an HTTP(S) URL, where `{rev}` is replaced by the nixpkgs revision (otherwise the
+    /// revision is appended as a path segment), or a `file://` URL / plain path to a
+    /// directory containing `<rev>.db` files, such as the output of `generate-db`.
+    /// If empty, the SnowflakeOS API is used.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub database_sources: Vec<String>,
+    /// Public keys (`name:base64`, as produced by `nix-store --generate-binary-cache-key`)
+    /// trusted to sign package databases. Every fetched database must come with a
+    /// SHA-256 manifest signed by one of these keys, see `allow_unsigned_databases`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_public_keys: Vec<String>,
+    /// Accept package databases without checking their signature. Unless this is
+    /// set, fetching a database fails when `trusted_public_keys` is empty.
+    #[serde(default)]
+    pub allow_unsigned_databases: bool,
+    /// Never use the network to fetch package databases or resolve revisions.
+    /// Only the cache and local `database_sources` are used.
+    #[serde(default)]
+    pub offline: bool,
+    /// Maximum size of the database cache in bytes. When set, databases of older
+    /// revisions are kept until the cache grows past this size, and the oldest are
+    /// evicted first. If not set, only the current, latest and pinned revisions are kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_size_limit: Option<u64>,
+    /// Base URL of the GitHub API, used to resolve branches of `github:` flakes.
+    /// If not set, `https://api.github.com` is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub github_api_url: Option<String>,
+    /// Token for the GitHub API, to avoid the rate limit on anonymous requests.
+    /// If not set, the `GITHUB_TOKEN` environment variable is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub github_token: Option<String>,
+    /// Base URL of the NixOS channel server. If not set, `https://channels.nixos.org` is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channels_url: Option<String>,
+    /// How long, in seconds, a channel or branch resolved to a revision is
+    /// remembered before asking the network again. 0 disables this. If not
+    /// set, one hour.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision_cache_ttl: Option<u64>,
 }
 
 impl LibSnowConfig {
@@ -115,11 +161,30 @@ impl LibSnowConfig {
         LibSnowConfig {
             flake: other.flake.or(self.flake),
             host: other.host.or(self.host),
+            flake_nixpkgs_input: other.flake_nixpkgs_input.or(self.flake_nixpkgs_input),
             generations: other.generations.or(self.generations),
             mode: other.mode,
             system_config_file: other.system_config_file.or(self.system_config_file),
             home_config_file: other.home_config_file.or(self.home_config_file),
             system_for_home_manager: other.system_for_home_manager || self.system_for_home_manager,
+            database_sources: if other.database_sources.is_empty() {
+                self.database_sources
+            } else {
+                other.database_sources
+            },
+            trusted_public_keys: if other.trusted_public_keys.is_empty() {
+                self.trusted_public_keys
+            } else {
+                other.trusted_public_keys
+            },
+            allow_unsigned_databases: other.allow_unsigned_databases
+                || self.allow_unsigned_databases,
+            offline: other.offline || self.offline,
+            cache_size_limit: other.cache_size_limit.or(self.cache_size_limit),
+            github_api_url: other.github_api_url.or(self.github_api_url),
+            github_token: other.github_token.or(self.github_token),
+            channels_url: other.channels_url.or(self.channels_url),
+            revision_cache_ttl: other.revision_cache_ttl.or(self.revision_cache_ttl),
         }
     }
 }
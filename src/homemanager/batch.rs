@@ -1,5 +1,5 @@
 use crate::{
-    Error, Result,
+    Error, NIXARCH, Result,
     config::configfile::{self, ConfigMode},
     homemanager::list::list,
     metadata::Metadata,
@@ -15,12 +15,12 @@ pub fn prepare(installs: &[&str], removes: &[&str], md: &Metadata) -> Result<Str
 
     let mut pkgs_to_install = vec![];
     for pkg in installs {
-        if md.get(pkg).is_ok() {
-            if installed.contains(&pkg.to_string()) {
-                debug!("{} is already installed", pkg);
-            } else {
-                pkgs_to_install.push(pkg.to_string());
-            }
+        let attr = md.resolve_installable(pkg)?;
+        if installed.contains(&attr) {
+            debug!("{} is already installed", attr);
+        } else if !pkgs_to_install.contains(&attr) {
+            md.ensure_available_on(&attr, &NIXARCH)?;
+            pkgs_to_install.push(attr);
         }
     }
 
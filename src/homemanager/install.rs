@@ -1,5 +1,5 @@
 use crate::{
-    Error, HELPER_EXEC, Result,
+    Error, HELPER_EXEC, NIXARCH, Result,
     config::configfile::{self, ConfigMode, LibSnowConfig},
     dbus,
     homemanager::list::list,
@@ -52,12 +52,12 @@ fn prepare_install(
 
     let mut pkgs_to_install = vec![];
     for pkg in pkgs {
-        if md.get(pkg).is_ok() {
-            if installed.contains(&pkg.to_string()) {
-                debug!("{} is already installed", pkg);
-            } else {
-                pkgs_to_install.push(pkg.to_string());
-            }
+        let attr = md.resolve_installable(pkg)?;
+        if installed.contains(&attr) {
+            debug!("{} is already installed", attr);
+        } else if !pkgs_to_install.contains(&attr) {
+            md.ensure_available_on(&attr, &NIXARCH)?;
+            pkgs_to_install.push(attr);
         }
     }
 
@@ -139,9 +139,20 @@ pub async fn install_spawn(
     let config = configfile::get_config()?;
     let (content, output_path, _arguments) = prepare_install(pkgs, md, &config)?;
 
+    spawn_config_helper(&content, &output_path, &config, auth_method).await
+}
+
+/// Spawn the helper that writes `content`, piped to its stdin, to
+/// `output_path` and rebuilds. Used by install and migrate.
+pub(crate) async fn spawn_config_helper(
+    content: &str,
+    output_path: &str,
+    config: &LibSnowConfig,
+    auth_method: AuthMethod<'_>,
+) -> Result<tokio::process::Child> {
     let mut child = tokio::process::Command::new(if config.system_for_home_manager {
         match auth_method {
-            AuthMethod::Dbus => unreachable!("D-Bus path handled in install()"),
+            AuthMethod::Dbus => unreachable!("D-Bus path handled by the caller"),
             AuthMethod::Sudo => "sudo",
             AuthMethod::Custom(cmd) => cmd,
         }
@@ -154,7 +165,7 @@ pub async fn install_spawn(
         vec!["config-home"]
     })
     .arg("--output")
-    .arg(&output_path)
+    .arg(output_path)
     .args(if let Some(generations) = config.get_generation_count() {
         vec!["--generations".to_string(), generations.to_string()]
     } else {
@@ -1,6 +1,7 @@
 pub mod batch;
 pub mod install;
 pub mod list;
+pub mod migrate;
 pub mod rebuild;
 pub mod remove;
 pub mod update;
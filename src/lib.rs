@@ -55,8 +55,25 @@ pub enum Error {
     #[error("nothing to do: {reason}")]
     NothingToDo { reason: String },
 
-    #[error("package not found: {attr}")]
-    PackageNotFound { attr: String },
+    #[error("package not found: {attr}{}", did_you_mean(.suggestions))]
+    PackageNotFound {
+        attr: String,
+        /// Likely intended attributes, best match first.
+        suggestions: Vec<Suggestion>,
+    },
+
+    #[error("package {attr} was removed: {message}")]
+    PackageRemoved { attr: String, message: String },
+
+    #[error(
+        "package {attr} is not available on {system} (supported platforms: {})",
+        .platforms.join(", ")
+    )]
+    UnsupportedPlatform {
+        attr: String,
+        system: String,
+        platforms: Vec<String>,
+    },
 
     #[error("HTTP {status}: {reason}")]
     HttpStatus { status: u16, reason: String },
@@ -66,6 +83,9 @@ pub enum Error {
 
     #[error("nix registry error: {reason}")]
     NixRegistry { reason: String },
+
+    #[error("flake.lock error: {reason}")]
+    FlakeLock { reason: String },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -86,10 +106,80 @@ pub struct PackageUpdate {
     pub old_version: String,
 }
 
+/// Where an attribute appears in a config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigEntry {
+    /// An entry in `environment.systemPackages` / `home.packages`.
+    Package,
+    /// A `programs.<name>.enable` option.
+    Program,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenamedAttr {
+    pub from: String,
+    pub from_entry: ConfigEntry,
+    pub to: String,
+    pub to_entry: ConfigEntry,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RemovedAttr {
+    pub attr: String,
+    pub entry: ConfigEntry,
+    pub message: String,
+}
+
+/// Result of migrating a config against a newer package database.
+/// Renames have been applied; removals need the user's attention.
+#[derive(Debug, Clone, Default)]
+pub struct MigrationReport {
+    pub renamed: Vec<RenamedAttr>,
+    pub removed: Vec<RemovedAttr>,
+}
+
+/// An attribute suggested in place of one that was not found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    pub attribute: String,
+    pub reason: SuggestionReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuggestionReason {
+    /// The attribute is an alias of, or differs only in case from, this one.
+    Alias,
+    /// This package has the requested name as its `pname`.
+    Pname,
+    /// This attribute name is a close spelling match.
+    Similar,
+}
+
+fn did_you_mean(suggestions: &[Suggestion]) -> String {
+    if suggestions.is_empty() {
+        return String::new();
+    }
+    let names: Vec<String> = suggestions
+        .iter()
+        .map(|s| format!("`{}`", s.attribute))
+        .collect();
+    format!(" (did you mean {}?)", names.join(", "))
+}
+
 #[derive(Debug, Clone)]
 pub enum PackageAttr {
-    NixPkgs { attr: String },
-    External { url: String, attr: String },
+    NixPkgs {
+        attr: String,
+    },
+    External {
+        url: String,
+        attr: String,
+    },
+    /// An enabled NixOS module, by option path, e.g. `services.tailscale`.
+    Module {
+        path: String,
+    },
 }
 
 impl fmt::Display for PackageAttr {
@@ -97,6 +187,7 @@ impl fmt::Display for PackageAttr {
         match self {
             PackageAttr::NixPkgs { attr } => write!(f, "{}", attr),
             PackageAttr::External { url, attr } => write!(f, "{}#{}", url, attr),
+            PackageAttr::Module { path } => write!(f, "{}", path),
         }
     }
 }
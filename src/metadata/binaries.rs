@@ -0,0 +1,71 @@
+use super::PkgInfo;
+use crate::Result;
+
+/// A package that provides a command, see [`Metadata::providers_of`](super::Metadata::providers_of).
+#[derive(Debug, Clone)]
+pub struct Provider {
+    pub package: PkgInfo,
+    /// Whether the command is the package's `meta.mainProgram`, rather than
+    /// one of the other binaries it ships.
+    pub main_program: bool,
+}
+
+impl Provider {
+    /// Sort key, best first: working packages, top-level attributes, packages
+    /// named after the command, main programs, then shorter attributes.
+    fn rank(&self, command: &str) -> impl Ord + '_ {
+        let attr = &self.package.attribute;
+        (
+            self.package.broken,
+            attr.contains('.'),
+            attr != command && self.package.pname != command,
+            !self.main_program,
+            attr.matches('.').count(),
+            attr.len(),
+            attr.as_str(),
+        )
+    }
+}
+
+const PROVIDER_COLUMNS: &str = "p.attribute, p.pname, p.version, m.description, \
+     m.broken, m.insecure, m.unfree";
+
+pub(crate) fn providers_of(conn: &rusqlite::Connection, command: &str) -> Result<Vec<Provider>> {
+    let has_binaries = conn
+        .prepare_cached("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'binaries'")?
+        .exists([])?;
+    // Older databases only know each package's mainProgram
+    let sql = if has_binaries {
+        format!(
+            "SELECT {PROVIDER_COLUMNS}, b.main_program FROM binaries b \
+             JOIN pkgs p ON p.attribute = b.attribute \
+             LEFT JOIN meta m ON p.attribute = m.attribute \
+             WHERE b.name = ?"
+        )
+    } else {
+        format!(
+            "SELECT {PROVIDER_COLUMNS}, 1 FROM meta m \
+             JOIN pkgs p ON p.attribute = m.attribute \
+             WHERE m.main_program = ?"
+        )
+    };
+
+    let mut stmt = conn.prepare_cached(&sql)?;
+    let rows = stmt.query_map([command], |row| {
+        Ok(Provider {
+            package: PkgInfo {
+                attribute: row.get(0)?,
+                pname: row.get(1)?,
+                version: row.get(2)?,
+                description: row.get(3)?,
+                broken: row.get::<_, Option<i64>>(4)?.unwrap_or(0) != 0,
+                insecure: row.get::<_, Option<i64>>(5)?.unwrap_or(0) != 0,
+                unfree: row.get::<_, Option<i64>>(6)?.unwrap_or(0) != 0,
+            },
+            main_program: row.get(7)?,
+        })
+    })?;
+    let mut providers = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+    providers.sort_by(|a, b| a.rank(command).cmp(&b.rank(command)));
+    Ok(providers)
+}
@@ -0,0 +1,135 @@
+use crate::Result;
+
+/// Split an attribute path prefix into the attribute set being completed
+/// (including its trailing dot) and the partial name inside it.
+fn split_level(prefix: &str) -> (&str, &str) {
+    match prefix.rfind('.') {
+        Some(i) => prefix.split_at(i + 1),
+        None => ("", prefix),
+    }
+}
+
+/// Exclusive upper bound of the attributes starting with `prefix`, using
+/// SQLite's byte-wise ordering of UTF-8 text.
+fn prefix_end(prefix: &str) -> String {
+    format!("{}\u{10FFFF}", prefix)
+}
+
+pub(crate) fn complete(
+    conn: &rusqlite::Connection,
+    prefix: &str,
+    limit: usize,
+) -> Result<Vec<String>> {
+    let (level, _) = split_level(prefix);
+    // Cut each match after the next `.` following `level`, so that nested
+    // attribute sets complete to `set.` rather than to their contents.
+    let mut stmt = conn.prepare_cached(
+        "SELECT c FROM ( \
+           SELECT DISTINCT CASE \
+             WHEN instr(substr(attribute, ?3 + 1), '.') > 0 \
+             THEN substr(attribute, 1, ?3 + instr(substr(attribute, ?3 + 1), '.')) \
+             ELSE attribute END AS c \
+           FROM pkgs WHERE attribute >= ?1 AND attribute < ?2 \
+         ) \
+         ORDER BY length(c) - length(replace(c, '.', '')), length(c), c \
+         LIMIT ?4",
+    )?;
+    let rows = stmt.query_map(
+        rusqlite::params![
+            prefix,
+            prefix_end(prefix),
+            level.chars().count() as i64,
+            limit as i64
+        ],
+        |row| row.get(0),
+    )?;
+    Ok(rows.collect::<rusqlite::Result<_>>()?)
+}
+
+/// An entry directly below an attribute set, see [`Metadata::children`](super::Metadata::children).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttrChild {
+    /// Full attribute path, e.g. `python3Packages.requests`.
+    pub attribute: String,
+    /// Last path segment, e.g. `requests`.
+    pub name: String,
+    pub kind: AttrChildKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttrChildKind {
+    Package,
+    /// A nested attribute set containing `packages` packages at any depth.
+    Set {
+        packages: usize,
+    },
+}
+
+/// One page of [`AttrChild`]ren, sorted by name.
+#[derive(Debug, Clone, Default)]
+pub struct AttrChildren {
+    pub children: Vec<AttrChild>,
+    /// Number of children across all pages.
+    pub total: usize,
+}
+
+// Split each attribute below `?3` characters of prefix into its next path
+// segment, and whether there is more after it.
+const CHILDREN_CTE: &str = "WITH rest AS ( \
+       SELECT substr(attribute, ?3 + 1) AS r FROM pkgs \
+       WHERE attribute >= ?1 AND attribute < ?2 \
+     ), seg AS ( \
+       SELECT CASE WHEN instr(r, '.') > 0 THEN substr(r, 1, instr(r, '.') - 1) ELSE r END AS name, \
+              instr(r, '.') > 0 AS is_set \
+       FROM rest \
+     ) ";
+
+pub(crate) fn children(
+    conn: &rusqlite::Connection,
+    prefix: &str,
+    offset: usize,
+    limit: usize,
+) -> Result<AttrChildren> {
+    let set = prefix.trim_end_matches('.');
+    let level = if set.is_empty() {
+        String::new()
+    } else {
+        format!("{}.", set)
+    };
+    let level_len = level.chars().count() as i64;
+    let end = prefix_end(&level);
+
+    let total: i64 = conn
+        .prepare_cached(&format!(
+            "{CHILDREN_CTE} SELECT count(*) FROM (SELECT DISTINCT name, is_set FROM seg)"
+        ))?
+        .query_row(rusqlite::params![level, end, level_len], |row| row.get(0))?;
+
+    let mut stmt = conn.prepare_cached(&format!(
+        "{CHILDREN_CTE} SELECT name, is_set, count(*) FROM seg \
+         GROUP BY name, is_set ORDER BY name, is_set LIMIT ?4 OFFSET ?5"
+    ))?;
+    let rows = stmt.query_map(
+        rusqlite::params![level, end, level_len, limit as i64, offset as i64],
+        |row| {
+            let name: String = row.get(0)?;
+            let kind = if row.get(1)? {
+                AttrChildKind::Set {
+                    packages: row.get::<_, i64>(2)? as usize,
+                }
+            } else {
+                AttrChildKind::Package
+            };
+            Ok(AttrChild {
+                attribute: format!("{}{}", level, name),
+                name,
+                kind,
+            })
+        },
+    )?;
+
+    Ok(AttrChildren {
+        children: rows.collect::<rusqlite::Result<_>>()?,
+        total: total as usize,
+    })
+}
@@ -0,0 +1,276 @@
+//! Inspect and manage the package databases and search indexes cached in
+//! [`CACHEDIR`](crate::CACHEDIR).
+//!
+//! The revisions last used by [`Metadata::connect`](super::Metadata::connect)
+//! and [`Metadata::connect_latest`](super::Metadata::connect_latest) are always
+//! kept, as are pinned revisions. Everything else is removed after each fetch,
+//! unless [`cache_size_limit`](crate::config::configfile::LibSnowConfig::cache_size_limit)
+//! is set, in which case the oldest revisions are evicted only once the cache
+//! grows past it.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use super::lock::{FileLock, db_lock_path, write_atomic};
+use super::search::index_dir_for_db_path;
+use crate::{CACHEDIR, Result, config::configfile};
+
+/// Contents of `cache.json`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub(crate) struct CacheState {
+    pub(crate) current_rev: String,
+    pub(crate) new_rev: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) pinned: Vec<String>,
+}
+
+impl CacheState {
+    fn keeps(&self, rev: &str) -> bool {
+        rev == self.current_rev || rev == self.new_rev || self.pinned.iter().any(|p| p == rev)
+    }
+}
+
+/// A package database in the cache.
+#[derive(Debug, Clone)]
+pub struct CachedRevision {
+    pub revision: String,
+    pub db_path: PathBuf,
+    /// Size of the database and its search index, in bytes.
+    pub size: u64,
+    pub modified: SystemTime,
+    pub has_index: bool,
+    pub pinned: bool,
+    /// Whether this is the current or latest revision known to libsnow.
+    pub in_use: bool,
+}
+
+impl CachedRevision {
+    /// Time since the database was last written.
+    pub fn age(&self) -> Duration {
+        self.modified.elapsed().unwrap_or_default()
+    }
+}
+
+fn cache_file_path() -> PathBuf {
+    Path::new(&*CACHEDIR).join("cache.json")
+}
+
+/// Held exclusively while `cache.json` is updated or the cache is cleaned up.
+async fn lock_cache() -> Result<FileLock> {
+    FileLock::exclusive_async(Path::new(&*CACHEDIR).join("cache.lock")).await
+}
+
+pub(crate) async fn read_state() -> Result<CacheState> {
+    match fs::read_to_string(cache_file_path()).await {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(CacheState::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Read-modify-write `cache.json` under the cache lock. `update` returns
+/// whether it changed anything.
+pub(crate) async fn update_state(update: impl FnOnce(&mut CacheState) -> bool) -> Result<()> {
+    let _lock = lock_cache().await?;
+    let mut state = read_state().await?;
+    if update(&mut state) {
+        write_atomic(
+            &cache_file_path(),
+            serde_json::to_string(&state)?.as_bytes(),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// List cached revisions, most recently written first.
+pub async fn list() -> Result<Vec<CachedRevision>> {
+    let state = read_state().await?;
+    let mut revisions = Vec::new();
+    let Ok(mut entries) = fs::read_dir(&*CACHEDIR).await else {
+        return Ok(revisions);
+    };
+
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("db") {
+            continue;
+        }
+        let Some(revision) = path.file_stem().map(|s| s.to_string_lossy().to_string()) else {
+            continue;
+        };
+        let meta = entry.metadata().await?;
+        let index_dir = index_dir_for_db_path(&path);
+        let has_index = index_dir.is_dir();
+        let index_size = if has_index {
+            dir_size(&index_dir).await?
+        } else {
+            0
+        };
+
+        revisions.push(CachedRevision {
+            pinned: state.pinned.contains(&revision),
+            in_use: revision == state.current_rev || revision == state.new_rev,
+            revision,
+            db_path: path,
+            size: meta.len() + index_size,
+            modified: meta.modified()?,
+            has_index,
+        });
+    }
+
+    revisions.sort_by_key(|r| std::cmp::Reverse(r.modified));
+    Ok(revisions)
+}
+
+/// Keep `rev` in the cache regardless of cleanup and the size limit.
+pub async fn pin(rev: &str) -> Result<()> {
+    update_state(|state| {
+        if state.pinned.iter().any(|p| p == rev) {
+            return false;
+        }
+        state.pinned.push(rev.to_string());
+        true
+    })
+    .await
+}
+
+/// Undo [`pin`]. The revision is removed on the next cleanup unless in use.
+pub async fn unpin(rev: &str) -> Result<()> {
+    update_state(|state| {
+        let len = state.pinned.len();
+        state.pinned.retain(|p| p != rev);
+        state.pinned.len() != len
+    })
+    .await
+}
+
+/// Revisions currently pinned.
+pub async fn pinned() -> Result<Vec<String>> {
+    Ok(read_state().await?.pinned)
+}
+
+/// Remove the database, search index and any partial download of `rev`,
+/// even if it is pinned or in use. Returns `false` without removing anything
+/// if the database is currently open or being downloaded by any process.
+pub async fn purge(rev: &str) -> Result<bool> {
+    let db_path = Path::new(&*CACHEDIR).join(format!("{}.db", rev));
+    let lock_path = db_lock_path(&db_path);
+    let Some(_lock) = FileLock::try_exclusive(&lock_path)? else {
+        return Ok(false);
+    };
+    remove_path(&db_path).await?;
+    let index_dir = index_dir_for_db_path(&db_path);
+    remove_path(&index_dir).await?;
+    remove_path(&index_dir.with_extension("index.lock")).await?;
+    remove_path(&PathBuf::from(format!("{}.part", db_path.display()))).await?;
+    remove_path(&lock_path).await?;
+    Ok(true)
+}
+
+/// Remove every cached database and index that is neither pinned nor in use.
+/// Databases another process has open are skipped. Returns the removed revisions.
+pub async fn purge_unused() -> Result<Vec<String>> {
+    let _lock = lock_cache().await?;
+    let state = read_state().await?;
+    let mut stale = Vec::new();
+    let Ok(mut entries) = fs::read_dir(&*CACHEDIR).await else {
+        return Ok(stale);
+    };
+
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        if ext != "db" && ext != "index" {
+            continue;
+        }
+        let stem = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        if !state.keeps(&stem) && !stale.contains(&stem) {
+            stale.push(stem);
+        }
+    }
+
+    let mut removed = Vec::new();
+    for rev in stale {
+        if purge(&rev).await? {
+            removed.push(rev);
+        }
+    }
+    Ok(removed)
+}
+
+/// Evict the oldest revisions that are neither pinned nor in use until the
+/// cache is at most `max_bytes`. Databases another process has open are
+/// skipped. Returns the removed revisions.
+pub async fn enforce_size_limit(max_bytes: u64) -> Result<Vec<String>> {
+    let _lock = lock_cache().await?;
+    let revisions = list().await?;
+    let mut total: u64 = revisions.iter().map(|r| r.size).sum();
+    let mut removed = Vec::new();
+
+    for rev in revisions.iter().rev() {
+        if total <= max_bytes {
+            break;
+        }
+        if rev.pinned || rev.in_use || !purge(&rev.revision).await? {
+            continue;
+        }
+        total -= rev.size;
+        removed.push(rev.revision.clone());
+    }
+    Ok(removed)
+}
+
+/// Apply the configured retention policy after a fetch.
+pub(crate) async fn cleanup() -> Result<()> {
+    let config = configfile::get_config().unwrap_or_default();
+    let removed = match config.cache_size_limit {
+        Some(limit) => enforce_size_limit(limit).await?,
+        None => purge_unused().await?,
+    };
+    if !removed.is_empty() {
+        tracing::debug!("Removed cached databases: {}", removed.join(", "));
+    }
+    Ok(())
+}
+
+async fn remove_path(path: &Path) -> Result<()> {
+    let result = if path.is_dir() {
+        fs::remove_dir_all(path).await
+    } else {
+        fs::remove_file(path).await
+    };
+    match result {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+async fn dir_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let meta = entry.metadata().await?;
+            if meta.is_dir() {
+                pending.push(entry.path());
+            } else {
+                size += meta.len();
+            }
+        }
+    }
+    Ok(size)
+}
@@ -1,102 +1,422 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::{Error, Result};
-use serde::{Deserialize, Serialize};
+use crate::{Error, Result, config::configfile};
+use async_compression::tokio::bufread::BrotliDecoder;
+use reqwest::{StatusCode, header};
 use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
 
-use super::search::index_dir_for_db_path;
+use super::cache;
+use super::fetch::{self, ConnectOptions, FetchRequest, FetchResponse, Fetcher};
+use super::lock::{FileLock, db_lock_path};
+use super::signing;
 use crate::CACHEDIR;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-struct DatabaseCache {
-    current_rev: String,
-    new_rev: String,
-}
+const DEFAULT_SOURCE: &str = "https://api.snowflakeos.org/libsnow/db/{rev}";
 
+#[derive(Debug, Clone, Copy)]
 pub(crate) enum DatabaseCacheEntry {
     Current,
     New,
 }
 
-pub(crate) async fn fetch_database(rev: &str, entry: DatabaseCacheEntry) -> Result<String> {
-    let cache_file_path = format!("{}/cache.json", &*CACHEDIR);
-    if !PathBuf::from(&cache_file_path).exists() {
-        fs::create_dir_all(&*CACHEDIR).await?;
-        fs::write(&cache_file_path, r#"{"current_rev": "", "new_rev": ""}"#).await?;
+/// Progress of a database download. Counts are bytes of the transfer, which
+/// may be compressed, and include any part resumed from an earlier attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadProgress {
+    pub received: u64,
+    pub total: Option<u64>,
+}
+
+/// Receives [`DownloadProgress`] updates while a database is fetched.
+/// Implemented for any `Fn(DownloadProgress)`.
+pub trait ProgressSink: Send + Sync {
+    fn report(&self, progress: DownloadProgress);
+}
+
+impl<F: Fn(DownloadProgress) + Send + Sync> ProgressSink for F {
+    fn report(&self, progress: DownloadProgress) {
+        self(progress)
+    }
+}
+
+/// A place package databases can be fetched from, see
+/// [`LibSnowConfig::database_sources`](crate::config::configfile::LibSnowConfig::database_sources).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum DatabaseSource {
+    Http(String),
+    Local(PathBuf),
+}
+
+impl DatabaseSource {
+    pub(crate) fn parse(source: &str) -> Self {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            DatabaseSource::Http(source.to_string())
+        } else {
+            DatabaseSource::Local(PathBuf::from(
+                source.strip_prefix("file://").unwrap_or(source),
+            ))
+        }
+    }
+
+    fn is_remote(&self) -> bool {
+        matches!(self, DatabaseSource::Http(_))
     }
-    let cache_content = fs::read_to_string(&cache_file_path).await?;
-    let mut cachejson: DatabaseCache = serde_json::from_str(&cache_content)?;
-    match entry {
+
+    /// Location of `<rev>.db`, or of a file published next to it when
+    /// `extension` is given (e.g. the manifest or signature).
+    fn location(&self, rev: &str, extension: Option<&str>) -> String {
+        let suffix = extension.map(|e| format!(".{}", e)).unwrap_or_default();
+        match self {
+            DatabaseSource::Http(url) => {
+                let url = if url.contains("{rev}") {
+                    url.replace("{rev}", rev)
+                } else {
+                    format!("{}/{}", url.trim_end_matches('/'), rev)
+                };
+                format!("{}{}", url, suffix)
+            }
+            DatabaseSource::Local(dir) => dir
+                .join(format!("{}.db{}", rev, suffix))
+                .to_string_lossy()
+                .to_string(),
+        }
+    }
+
+    async fn read(
+        &self,
+        fetcher: &dyn Fetcher,
+        rev: &str,
+        extension: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        let location = self.location(rev, extension);
+        match self {
+            DatabaseSource::Http(_) => fetch::get(fetcher, FetchRequest::new(location)).await,
+            DatabaseSource::Local(_) => Ok(fs::read(&location).await?),
+        }
+    }
+
+    /// Fetch `<rev>.db` into `<outpath>.part`, validate it and only then
+    /// move it to `outpath`. The manifest and signature are fetched first, so
+    /// an unsigned database is rejected before it is downloaded. A failed
+    /// download keeps the partial file so the next attempt can resume it; a
+    /// file that fails validation is removed.
+    async fn fetch(
+        &self,
+        rev: &str,
+        outpath: &str,
+        config: &configfile::LibSnowConfig,
+        options: &ConnectOptions<'_>,
+    ) -> Result<()> {
+        let signed = if config.allow_unsigned_databases {
+            None
+        } else {
+            let manifest = self
+                .read_sidecar(options.fetcher, rev, signing::MANIFEST_EXTENSION)
+                .await?;
+            let signature = self
+                .read_sidecar(options.fetcher, rev, signing::SIGNATURE_EXTENSION)
+                .await?;
+            Some((manifest, signature))
+        };
+
+        let part = PathBuf::from(format!("{}.part", outpath));
+        let location = self.location(rev, None);
+        let progress = options.progress;
+        let bytes = match self {
+            DatabaseSource::Http(_) => {
+                download_to(options.fetcher, &location, &part, progress).await?
+            }
+            DatabaseSource::Local(_) => {
+                let bytes = fs::read(&location).await?;
+                let len = bytes.len() as u64;
+                progress.report(DownloadProgress {
+                    received: len,
+                    total: Some(len),
+                });
+                fs::write(&part, &bytes).await?;
+                bytes
+            }
+        };
+
+        if let Err(err) = validate(rev, &bytes, signed.as_ref(), config) {
+            let _ = fs::remove_file(&part).await;
+            return Err(err);
+        }
+        fs::rename(&part, outpath).await?;
+        Ok(())
+    }
+
+    async fn read_sidecar(
+        &self,
+        fetcher: &dyn Fetcher,
+        rev: &str,
+        extension: &str,
+    ) -> Result<String> {
+        let bytes = self
+            .read(fetcher, rev, Some(extension))
+            .await
+            .map_err(|e| Error::InvalidDatabase {
+                reason: format!("missing database .{} file (rev: {}): {}", extension, rev, e),
+            })?;
+        String::from_utf8(bytes).map_err(|_| Error::InvalidDatabase {
+            reason: format!(
+                "database .{} file is not valid UTF-8 (rev: {})",
+                extension, rev
+            ),
+        })
+    }
+}
+
+/// Check that `bytes` is a SQLite database and, unless unsigned databases are
+/// allowed, that it matches the signed manifest.
+fn validate(
+    rev: &str,
+    bytes: &[u8],
+    signed: Option<&(String, String)>,
+    config: &configfile::LibSnowConfig,
+) -> Result<()> {
+    if bytes.len() < 16 || &bytes[..16] != b"SQLite format 3\0" {
+        return Err(Error::InvalidDatabase {
+            reason: format!("fetched file is not a valid SQLite database (rev: {})", rev),
+        });
+    }
+    match signed {
+        Some((manifest, signature)) => {
+            signing::verify_database(bytes, rev, manifest, signature, &config.trusted_public_keys)
+        }
+        None => Ok(()),
+    }
+}
+
+/// The configured database sources, in the order they should be tried.
+pub(crate) fn database_sources(config: &configfile::LibSnowConfig) -> Vec<DatabaseSource> {
+    if config.database_sources.is_empty() {
+        vec![DatabaseSource::parse(DEFAULT_SOURCE)]
+    } else {
+        config
+            .database_sources
+            .iter()
+            .map(|s| DatabaseSource::parse(s))
+            .collect()
+    }
+}
+
+/// Whether the user has asked libsnow to stay off the network.
+pub(crate) fn is_offline() -> bool {
+    configfile::get_config().map(|c| c.offline).unwrap_or(false)
+}
+
+/// The revision last recorded in `cache.json` for `entry`, if any.
+pub(crate) async fn cached_revision(entry: DatabaseCacheEntry) -> Option<String> {
+    let cachejson = cache::read_state().await.ok()?;
+    let rev = match entry {
+        DatabaseCacheEntry::Current => cachejson.current_rev,
+        DatabaseCacheEntry::New => cachejson.new_rev,
+    };
+    if rev.is_empty() { None } else { Some(rev) }
+}
+
+/// Make sure the database for `rev` is in the cache and return its path,
+/// together with a shared lock that keeps cleanup in other processes from
+/// removing it until the caller has opened it.
+///
+/// Only one process downloads a revision at a time; others wait for it and
+/// reuse the result.
+pub(crate) async fn fetch_database(
+    rev: &str,
+    entry: DatabaseCacheEntry,
+    options: &ConnectOptions<'_>,
+) -> Result<(String, FileLock)> {
+    cache::update_state(|cachejson| match entry {
         DatabaseCacheEntry::Current if rev != cachejson.current_rev => {
             cachejson.current_rev = rev.to_string();
+            true
         }
         DatabaseCacheEntry::New if rev != cachejson.new_rev => {
             cachejson.new_rev = rev.to_string();
+            true
         }
-        _ => {}
-    }
-    fs::write(&cache_file_path, serde_json::to_string(&cachejson)?).await?;
+        _ => false,
+    })
+    .await?;
 
     let outpath = format!("{}/{}.db", &*CACHEDIR, rev);
+    let lock_path = db_lock_path(Path::new(&outpath));
 
-    if PathBuf::from(&outpath).exists() {
-        cleanup(&outpath, &cachejson).await?;
-        return Ok(outpath);
+    loop {
+        // Waits for any download of this revision in progress elsewhere
+        let lock = FileLock::shared_async(lock_path.clone()).await?;
+        if PathBuf::from(&outpath).exists() {
+            cache::cleanup().await?;
+            return Ok((outpath, lock));
+        }
+        drop(lock);
+
+        let lock = FileLock::exclusive_async(lock_path.clone()).await?;
+        if !PathBuf::from(&outpath).exists()
+            && let Err(err) = download_database(rev, &outpath, options).await
+        {
+            drop(lock);
+            if !is_unavailable(&err) {
+                return Err(err);
+            }
+            return fallback_database().await.ok_or(err);
+        }
     }
+}
 
-    match download_database(rev, &outpath).await {
-        Ok(()) => {
-            cleanup(&outpath, &cachejson).await?;
-            Ok(outpath)
+async fn download_database(rev: &str, outpath: &str, options: &ConnectOptions<'_>) -> Result<()> {
+    let config = configfile::get_config().unwrap_or_default();
+    if !config.allow_unsigned_databases && config.trusted_public_keys.is_empty() {
+        return Err(Error::Config {
+            reason: "no trusted public keys configured to verify databases; \
+                     set trusted_public_keys or allow_unsigned_databases"
+                .into(),
+        });
+    }
+    let mut last_err = None;
+    let mut invalid = None;
+    for source in database_sources(&config) {
+        if config.offline && source.is_remote() {
+            continue;
         }
-        Err(err) => {
-            tracing::warn!("Failed to fetch database for rev {rev}: {err}");
-            if let Some(fallback) = find_newest_cached_db().await {
-                tracing::info!("Falling back to cached database: {}", fallback);
-                Ok(fallback)
-            } else {
-                Err(err)
+        match source.fetch(rev, outpath, &config, options).await {
+            Ok(()) => return Ok(()),
+            Err(err) => {
+                tracing::warn!("Failed to fetch database for rev {rev} from {source:?}: {err}");
+                if is_unavailable(&err) {
+                    last_err = Some(err);
+                } else {
+                    invalid.get_or_insert(err);
+                }
             }
         }
     }
+    Err(invalid.or(last_err).unwrap_or_else(|| Error::Config {
+        reason: format!("no database source available for rev {}", rev),
+    }))
 }
 
-async fn download_database(rev: &str, outpath: &str) -> Result<()> {
-    let client = reqwest::Client::builder().brotli(true).build()?;
-    let output = client
-        .get(format!("https://api.snowflakeos.org/libsnow/db/{}", rev))
-        .send()
-        .await?;
+/// Whether `err` means no database could be fetched (network or I/O failure,
+/// offline with no local source, or no keys to verify one), as opposed to a
+/// database that was fetched but rejected. Only the former may fall back to a cached database.
+fn is_unavailable(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::Io(_) | Error::Http(_) | Error::HttpStatus { .. } | Error::Config { .. }
+    )
+}
 
-    let status = output.status();
-    if !status.is_success() {
-        return Err(Error::HttpStatus {
-            status: status.as_u16(),
-            reason: "failed to fetch database".into(),
-        });
+/// The newest cached database, locked for reading.
+async fn fallback_database() -> Option<(String, FileLock)> {
+    let fallback = find_newest_cached_db().await?;
+    let lock = FileLock::shared_async(db_lock_path(Path::new(&fallback)))
+        .await
+        .ok()?;
+    if !Path::new(&fallback).exists() {
+        return None;
     }
+    tracing::info!("Falling back to cached database: {}", fallback);
+    Some((fallback, lock))
+}
 
-    fs::create_dir_all(
-        PathBuf::from(outpath)
-            .parent()
-            .ok_or_else(|| Error::Config {
-                reason: "invalid path".into(),
-            })?,
-    )
-    .await?;
-    let bytes = output.bytes().await?;
+/// Stream `url` into `part`, resuming from its current length with a Range
+/// request when it already exists. Returns the decoded database bytes, which
+/// are also left in `part`.
+async fn download_to(
+    fetcher: &dyn Fetcher,
+    url: &str,
+    part: &Path,
+    progress: &dyn ProgressSink,
+) -> Result<Vec<u8>> {
+    // Ask for brotli but decode it ourselves: ranges refer to the encoded
+    // bytes, so the partial file has to hold the body as sent.
+    if let Some(parent) = part.parent() {
+        fs::create_dir_all(parent).await?;
+    }
 
-    if bytes.len() < 16 || &bytes[..16] != b"SQLite format 3\0" {
-        return Err(Error::InvalidDatabase {
-            reason: format!(
-                "downloaded file is not a valid SQLite database (rev: {})",
-                rev
-            ),
-        });
+    let mut offset = fs::metadata(part).await.map(|m| m.len()).unwrap_or(0);
+    let mut output = loop {
+        let mut request = FetchRequest::new(url).header(header::ACCEPT_ENCODING.as_str(), "br");
+        if offset > 0 {
+            request = request.header(header::RANGE.as_str(), format!("bytes={}-", offset));
+        }
+        let output = fetcher.fetch(request).await?;
+        let status = output.status;
+
+        if offset > 0 && status == StatusCode::PARTIAL_CONTENT.as_u16() {
+            if content_range_start(&output) == Some(offset) {
+                break output;
+            }
+        } else if output.is_success() {
+            offset = 0;
+            break output;
+        } else if offset == 0 || status != StatusCode::RANGE_NOT_SATISFIABLE.as_u16() {
+            return Err(Error::HttpStatus {
+                status,
+                reason: "failed to fetch database".into(),
+            });
+        }
+
+        // The partial file does not line up with what the server has, start over
+        tracing::debug!("Discarding partial download {}", part.display());
+        let _ = fs::remove_file(part).await;
+        offset = 0;
+    };
+
+    let encoding = output
+        .header(header::CONTENT_ENCODING.as_str())
+        .unwrap_or("identity")
+        .to_ascii_lowercase();
+    let total = output.content_length().map(|len| len + offset);
+
+    let mut file = if offset > 0 {
+        fs::OpenOptions::new().append(true).open(part).await?
+    } else {
+        fs::File::create(part).await?
+    };
+    let mut received = offset;
+    progress.report(DownloadProgress { received, total });
+    while let Some(chunk) = output.body.chunk().await? {
+        file.write_all(&chunk).await?;
+        received += chunk.len() as u64;
+        progress.report(DownloadProgress { received, total });
     }
+    file.flush().await?;
+    drop(file);
+
+    match encoding.as_str() {
+        "identity" => Ok(fs::read(part).await?),
+        "br" => {
+            let mut bytes = Vec::new();
+            let file = fs::File::open(part).await?;
+            let decoded = BrotliDecoder::new(BufReader::new(file))
+                .read_to_end(&mut bytes)
+                .await;
+            if let Err(e) = decoded {
+                let _ = fs::remove_file(part).await;
+                return Err(Error::InvalidDatabase {
+                    reason: format!("failed to decompress database: {}", e),
+                });
+            }
+            fs::write(part, &bytes).await?;
+            Ok(bytes)
+        }
+        other => {
+            let _ = fs::remove_file(part).await;
+            Err(Error::InvalidDatabase {
+                reason: format!("unsupported content encoding: {}", other),
+            })
+        }
+    }
+}
 
-    fs::write(outpath, &bytes).await?;
-    Ok(())
+/// Start offset of a `Content-Range: bytes <start>-<end>/<total>` header.
+fn content_range_start(response: &FetchResponse) -> Option<u64> {
+    let range = response.header(header::CONTENT_RANGE.as_str())?;
+    let (start, _) = range.strip_prefix("bytes ")?.split_once('-')?;
+    start.trim().parse().ok()
 }
 
 async fn find_newest_cached_db() -> Option<String> {
@@ -121,45 +441,3 @@ async fn find_newest_cached_db() -> Option<String> {
 
     newest.map(|(_, p)| p)
 }
-
-async fn cleanup(outpath: &str, cachejson: &DatabaseCache) -> Result<()> {
-    // Clean up old databases and their search indexes
-    let cache_dir = format!("{}/", &*CACHEDIR);
-    let mut entries = fs::read_dir(&cache_dir).await?;
-    while let Ok(Some(entry)) = entries.next_entry().await {
-        let path = entry.path();
-        let ext = path
-            .extension()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-
-        if ext != "db" && ext != "index" {
-            continue;
-        }
-
-        let stem = path
-            .file_stem()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-
-        // Keep entries belonging to current or new revision
-        if stem == cachejson.current_rev || stem == cachejson.new_rev {
-            continue;
-        }
-
-        // Keep the outpath itself and its sibling index
-        let outpath_pb = PathBuf::from(outpath);
-        if path == outpath_pb || path == index_dir_for_db_path(&outpath_pb) {
-            continue;
-        }
-
-        if path.is_dir() {
-            let _ = fs::remove_dir_all(&path).await;
-        } else {
-            let _ = fs::remove_file(&path).await;
-        }
-    }
-    Ok(())
-}
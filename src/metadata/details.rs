@@ -0,0 +1,147 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::platform::Platform;
+
+/// Full metadata for a single package, as stored in the `meta` table.
+#[derive(Debug, Clone)]
+pub struct PkgDetails {
+    pub attribute: String,
+    pub pname: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub long_description: Option<String>,
+    pub homepage: Vec<String>,
+    pub download_page: Option<String>,
+    pub changelog: Option<String>,
+    pub licenses: Vec<License>,
+    pub maintainers: Vec<Maintainer>,
+    pub main_program: Option<String>,
+    pub platforms: Vec<Platform>,
+    pub bad_platforms: Vec<Platform>,
+    pub broken: bool,
+    pub insecure: bool,
+    pub unfree: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct License {
+    pub spdx_id: Option<String>,
+    pub short_name: Option<String>,
+    pub full_name: Option<String>,
+    /// Whether the license is free, following the `lib.licenses` default of
+    /// free when unset. `None` for licenses given as a bare string, which are
+    /// not assumed free.
+    pub free: Option<bool>,
+    /// Whether the package may be redistributed, defaulting to `free` like
+    /// `lib.licenses`. `None` for licenses given as a bare string.
+    pub redistributable: Option<bool>,
+}
+
+impl License {
+    /// Lowercase license family, e.g. `GPL-3.0-or-later` -> `gpl`, `bsd3` -> `bsd`.
+    pub fn family(&self) -> Option<String> {
+        let id = self
+            .spdx_id
+            .as_deref()
+            .or(self.short_name.as_deref())
+            .or(self.full_name.as_deref())?;
+        let head = id.split(['-', '.', ' ']).next().unwrap_or(id);
+        let name = head
+            .chars()
+            .take_while(|c| c.is_ascii_alphabetic())
+            .collect::<String>()
+            .to_lowercase();
+        let name = name
+            .strip_suffix("plus")
+            .or_else(|| name.strip_suffix("only"))
+            .unwrap_or(&name)
+            .to_string();
+        if name.is_empty() { None } else { Some(name) }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Maintainer {
+    #[serde(default)]
+    pub name: String,
+    pub github: Option<String>,
+    pub email: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LicenseJson {
+    #[serde(rename = "spdxId")]
+    spdx_id: Option<String>,
+    #[serde(rename = "shortName")]
+    short_name: Option<String>,
+    #[serde(rename = "fullName")]
+    full_name: Option<String>,
+    free: Option<bool>,
+    redistributable: Option<bool>,
+}
+
+/// Parse a JSON column as stored by `generate-db`. Missing values are
+/// written as empty strings, so those are treated as absent.
+pub(crate) fn parse_json_column(column: Option<&str>) -> Option<Value> {
+    column
+        .filter(|s| !s.is_empty())
+        .and_then(|s| serde_json::from_str(s).ok())
+}
+
+fn parse_license(value: &Value) -> Option<License> {
+    match value {
+        // Licenses given as a bare string are not part of `lib.licenses`,
+        // so nothing is known about them beyond the name.
+        Value::String(s) => Some(License {
+            spdx_id: None,
+            short_name: Some(s.clone()),
+            full_name: None,
+            free: None,
+            redistributable: None,
+        }),
+        Value::Object(_) => {
+            let l: LicenseJson = serde_json::from_value(value.clone()).ok()?;
+            let free = l.free.unwrap_or(true);
+            Some(License {
+                spdx_id: l.spdx_id,
+                short_name: l.short_name,
+                full_name: l.full_name,
+                free: Some(free),
+                redistributable: Some(l.redistributable.unwrap_or(free)),
+            })
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_licenses(value: &Value) -> Vec<License> {
+    match value {
+        Value::Array(arr) => arr.iter().filter_map(parse_license).collect(),
+        v => parse_license(v).into_iter().collect(),
+    }
+}
+
+pub(crate) fn parse_maintainers(value: &Value) -> Vec<Maintainer> {
+    match value {
+        Value::Array(arr) => arr
+            .iter()
+            .filter_map(|m| serde_json::from_value(m.clone()).ok())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Collect string values from a field that nixpkgs allows to be either
+/// a single string or a list of strings (e.g. `meta.homepage`).
+pub(crate) fn parse_strings(value: &Value) -> Vec<String> {
+    match value {
+        Value::Array(arr) => arr
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        Value::String(s) => vec![s.clone()],
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use super::{AliasInfo, AliasKind};
+use crate::{PackageAttr, PackageUpdate, Result};
+
+/// Changes between two package database revisions.
+#[derive(Debug, Clone, Default)]
+pub struct RevisionDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<PackageUpdate>,
+    pub newly_broken: Vec<String>,
+    pub newly_insecure: Vec<String>,
+    pub new_aliases: Vec<AliasInfo>,
+}
+
+const SCHEMA: &str = "diff_other";
+
+pub(crate) fn diff(conn: &rusqlite::Connection, other: &Path) -> Result<RevisionDiff> {
+    conn.execute(
+        &format!("ATTACH DATABASE ?1 AS {}", SCHEMA),
+        [other.to_string_lossy()],
+    )?;
+    let result = query_diff(conn);
+    conn.execute(&format!("DETACH DATABASE {}", SCHEMA), [])?;
+    result
+}
+
+fn query_attrs(conn: &rusqlite::Connection, sql: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    Ok(rows.collect::<rusqlite::Result<_>>()?)
+}
+
+fn query_diff(conn: &rusqlite::Connection) -> Result<RevisionDiff> {
+    let added = query_attrs(
+        conn,
+        &format!(
+            "SELECT n.attribute FROM {SCHEMA}.pkgs n \
+             WHERE NOT EXISTS (SELECT 1 FROM main.pkgs o WHERE o.attribute = n.attribute) \
+             ORDER BY n.attribute"
+        ),
+    )?;
+
+    let removed = query_attrs(
+        conn,
+        &format!(
+            "SELECT o.attribute FROM main.pkgs o \
+             WHERE NOT EXISTS (SELECT 1 FROM {SCHEMA}.pkgs n WHERE n.attribute = o.attribute) \
+             ORDER BY o.attribute"
+        ),
+    )?;
+
+    let changed = {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT o.attribute, o.version, n.version \
+             FROM main.pkgs o JOIN {SCHEMA}.pkgs n ON o.attribute = n.attribute \
+             WHERE o.version IS NOT n.version \
+             ORDER BY o.attribute"
+        ))?;
+        let rows = stmt.query_map([], |row| {
+            Ok(PackageUpdate {
+                attr: PackageAttr::NixPkgs { attr: row.get(0)? },
+                old_version: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                new_version: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+            })
+        })?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+
+    let newly = |column: &str| {
+        query_attrs(
+            conn,
+            &format!(
+                "SELECT n.attribute FROM {SCHEMA}.meta n \
+                 JOIN main.meta o ON o.attribute = n.attribute \
+                 WHERE n.{column} = 1 AND COALESCE(o.{column}, 0) = 0 \
+                 ORDER BY n.attribute"
+            ),
+        )
+    };
+    let newly_broken = newly("broken")?;
+    let newly_insecure = newly("insecure")?;
+
+    let new_aliases = {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT n.alias, n.type, n.replacement, n.message FROM {SCHEMA}.aliases n \
+             WHERE NOT EXISTS (SELECT 1 FROM main.aliases o WHERE o.alias = n.alias) \
+             ORDER BY n.alias"
+        ))?;
+        let rows = stmt.query_map([], |row| {
+            Ok(AliasInfo {
+                attribute: row.get(0)?,
+                kind: AliasKind::from_row(row.get(1)?, row.get(2)?, row.get(3)?),
+            })
+        })?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+
+    Ok(RevisionDiff {
+        added,
+        removed,
+        changed,
+        newly_broken,
+        newly_insecure,
+        new_aliases,
+    })
+}
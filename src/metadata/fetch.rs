@@ -0,0 +1,187 @@
+//! HTTP transport used to resolve revisions and download package databases.
+//!
+//! Everything libsnow fetches over the network goes through a [`Fetcher`].
+//! The default, [`ReqwestFetcher`], can be swapped for another implementation
+//! with [`ConnectOptions`], e.g. to serve fixtures in tests or to route
+//! requests through an application's own client.
+
+use std::{future::Future, pin::Pin, sync::LazyLock};
+
+use super::database::{DownloadProgress, ProgressSink};
+use crate::{Error, Result};
+
+/// A boxed future, so that [`Fetcher`] can be used as a trait object.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A GET request.
+#[derive(Debug, Clone, Default)]
+pub struct FetchRequest {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl FetchRequest {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+/// A response body, read one chunk at a time.
+pub trait FetchBody: Send {
+    /// The next chunk of the body, or `None` at the end.
+    fn chunk(&mut self) -> BoxFuture<'_, Result<Option<Vec<u8>>>>;
+}
+
+/// The response to a [`FetchRequest`]. The body is returned as sent:
+/// implementations must not decode any `Content-Encoding` themselves.
+pub struct FetchResponse {
+    pub status: u16,
+    /// Header names are lowercase.
+    pub headers: Vec<(String, String)>,
+    pub body: Box<dyn FetchBody>,
+}
+
+impl FetchResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// The first value of header `name` (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Length of the body, from `Content-Length`.
+    pub fn content_length(&self) -> Option<u64> {
+        self.header("content-length")?.trim().parse().ok()
+    }
+
+    /// Read the whole body.
+    pub async fn bytes(mut self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        while let Some(chunk) = self.body.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes)
+    }
+
+    /// Fail with [`Error::HttpStatus`] unless the status is 2xx.
+    pub fn error_for_status(self, reason: &str) -> Result<Self> {
+        if self.is_success() {
+            Ok(self)
+        } else {
+            Err(Error::HttpStatus {
+                status: self.status,
+                reason: reason.to_string(),
+            })
+        }
+    }
+}
+
+/// Performs HTTP GET requests for libsnow.
+pub trait Fetcher: Send + Sync {
+    fn fetch(&self, request: FetchRequest) -> BoxFuture<'_, Result<FetchResponse>>;
+}
+
+/// The default [`Fetcher`], backed by a shared [`reqwest::Client`].
+#[derive(Debug, Clone)]
+pub struct ReqwestFetcher {
+    client: reqwest::Client,
+}
+
+impl ReqwestFetcher {
+    pub fn new() -> Result<Self> {
+        // Bodies are decoded by the caller, see `FetchResponse`
+        let client = reqwest::Client::builder()
+            .no_brotli()
+            .user_agent("libsnow")
+            .build()?;
+        Ok(Self::with_client(client))
+    }
+
+    /// Use an existing client. It must not decode response bodies itself.
+    pub fn with_client(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+struct ReqwestBody(reqwest::Response);
+
+impl FetchBody for ReqwestBody {
+    fn chunk(&mut self) -> BoxFuture<'_, Result<Option<Vec<u8>>>> {
+        Box::pin(async move { Ok(self.0.chunk().await?.map(|b| b.to_vec())) })
+    }
+}
+
+impl Fetcher for ReqwestFetcher {
+    fn fetch(&self, request: FetchRequest) -> BoxFuture<'_, Result<FetchResponse>> {
+        Box::pin(async move {
+            let mut builder = self.client.get(&request.url);
+            for (name, value) in &request.headers {
+                builder = builder.header(name, value);
+            }
+            let response = builder.send().await?;
+            let headers = response
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((name.as_str().to_string(), value.to_str().ok()?.to_string()))
+                })
+                .collect();
+            Ok(FetchResponse {
+                status: response.status().as_u16(),
+                headers,
+                body: Box::new(ReqwestBody(response)),
+            })
+        })
+    }
+}
+
+static DEFAULT_FETCHER: LazyLock<ReqwestFetcher> =
+    LazyLock::new(|| ReqwestFetcher::new().expect("failed to initialize the default HTTP client"));
+
+struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn report(&self, _progress: DownloadProgress) {}
+}
+
+/// How [`Metadata::connect_with`](super::Metadata::connect_with) and friends
+/// reach the network.
+#[derive(Clone, Copy)]
+pub struct ConnectOptions<'a> {
+    pub fetcher: &'a dyn Fetcher,
+    /// Receives database download progress.
+    pub progress: &'a dyn ProgressSink,
+}
+
+impl Default for ConnectOptions<'_> {
+    fn default() -> Self {
+        Self {
+            fetcher: &*DEFAULT_FETCHER,
+            progress: &NoProgress,
+        }
+    }
+}
+
+/// GET `url` and return the body, failing on non-2xx responses.
+pub(crate) async fn get(fetcher: &dyn Fetcher, request: FetchRequest) -> Result<Vec<u8>> {
+    let url = request.url.clone();
+    fetcher
+        .fetch(request)
+        .await?
+        .error_for_status(&format!("failed to fetch {}", url))?
+        .bytes()
+        .await
+}
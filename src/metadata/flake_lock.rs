@@ -0,0 +1,211 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::Deserialize;
+
+use super::revision::RevisionInfo;
+use crate::{Error, Result};
+
+/// Input used when no input name is configured.
+pub(crate) const DEFAULT_NIXPKGS_INPUT: &str = "nixpkgs";
+
+/// `follows` chains longer than this are treated as cycles.
+const MAX_FOLLOWS_DEPTH: usize = 32;
+
+#[derive(Debug, Deserialize)]
+struct FlakeLock {
+    nodes: HashMap<String, LockNode>,
+    root: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LockNode {
+    #[serde(default)]
+    inputs: HashMap<String, LockInput>,
+    locked: Option<LockedRef>,
+    original: Option<OriginalRef>,
+}
+
+/// An input is either a node name, or a `follows` path of input names
+/// starting at the root flake.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum LockInput {
+    Node(String),
+    Follows(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+struct LockedRef {
+    rev: Option<String>,
+    #[serde(rename = "lastModified")]
+    last_modified: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OriginalRef {
+    #[serde(rename = "ref")]
+    git_ref: Option<String>,
+}
+
+fn lock_error(reason: impl Into<String>) -> Error {
+    Error::FlakeLock {
+        reason: reason.into(),
+    }
+}
+
+impl FlakeLock {
+    fn node(&self, name: &str) -> Result<&LockNode> {
+        self.nodes
+            .get(name)
+            .ok_or_else(|| lock_error(format!("missing node {}", name)))
+    }
+
+    /// Node name of input `input` of node `from`.
+    fn resolve_input(&self, from: &str, input: &str, depth: usize) -> Result<&str> {
+        match self.node(from)?.inputs.get(input) {
+            Some(LockInput::Node(node)) => Ok(node),
+            Some(LockInput::Follows(path)) => self.resolve_path(path, depth + 1),
+            None => Err(lock_error(format!("no input {} in {}", input, from))),
+        }
+    }
+
+    /// Node name at the end of a path of input names from the root.
+    fn resolve_path(&self, path: &[String], depth: usize) -> Result<&str> {
+        if depth > MAX_FOLLOWS_DEPTH {
+            return Err(lock_error(format!("follows cycle at {}", path.join("/"))));
+        }
+        let mut node = self.root.as_str();
+        for input in path {
+            node = self.resolve_input(node, input, depth)?;
+        }
+        Ok(node)
+    }
+}
+
+/// `nixos-24.11` or `release-24.11` -> `24.11`.
+pub(crate) fn release_from_ref(git_ref: &str) -> Option<String> {
+    let version = git_ref
+        .strip_prefix("nixos-")
+        .or_else(|| git_ref.strip_prefix("release-"))?;
+    let (major, minor) = version.split_once('.')?;
+    let is_num = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    (is_num(major) && is_num(minor)).then(|| version.to_string())
+}
+
+/// Parse `flake.lock` contents and find the revision locked for `input`.
+/// Nested inputs are written as a path, e.g. `home-manager/nixpkgs`.
+pub(crate) fn locked_revision(contents: &str, input: &str) -> Result<RevisionInfo> {
+    let lock: FlakeLock = serde_json::from_str(contents).map_err(|e| lock_error(e.to_string()))?;
+    let path: Vec<String> = input.split('/').map(str::to_string).collect();
+    let name = lock.resolve_path(&path, 0)?;
+    let node = lock.node(name)?;
+    let locked = node
+        .locked
+        .as_ref()
+        .ok_or_else(|| lock_error(format!("input {} is not locked", input)))?;
+    let rev = locked
+        .rev
+        .clone()
+        .ok_or_else(|| lock_error(format!("input {} has no locked revision", input)))?;
+
+    Ok(RevisionInfo {
+        nixpkgs_revision: rev,
+        nixos_release: node
+            .original
+            .as_ref()
+            .and_then(|o| o.git_ref.as_deref())
+            .and_then(release_from_ref),
+        last_modified: locked.last_modified,
+    })
+}
+
+/// Read `flake.lock` in `flake_dir`. `None` if the flake has not been locked yet.
+pub(crate) async fn read_locked_revision(
+    flake_dir: &Path,
+    input: &str,
+) -> Result<Option<RevisionInfo>> {
+    match tokio::fs::read_to_string(flake_dir.join("flake.lock")).await {
+        Ok(contents) => locked_revision(&contents, input).map(Some),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCK: &str = r#"{
+  "nodes": {
+    "home-manager": {
+      "inputs": { "nixpkgs": ["nixpkgs"] },
+      "locked": {
+        "owner": "nix-community",
+        "repo": "home-manager",
+        "rev": "1111111111111111111111111111111111111111",
+        "type": "github"
+      },
+      "original": { "owner": "nix-community", "repo": "home-manager", "type": "github" }
+    },
+    "nixpkgs": {
+      "locked": {
+        "lastModified": 1735000000,
+        "owner": "NixOS",
+        "repo": "nixpkgs",
+        "rev": "2222222222222222222222222222222222222222",
+        "type": "github"
+      },
+      "original": { "owner": "NixOS", "ref": "nixos-24.11", "repo": "nixpkgs", "type": "github" }
+    },
+    "root": {
+      "inputs": { "home-manager": "home-manager", "nixpkgs": "nixpkgs" }
+    }
+  },
+  "root": "root",
+  "version": 7
+}"#;
+
+    #[test]
+    fn locked_input() {
+        let info = locked_revision(LOCK, DEFAULT_NIXPKGS_INPUT).unwrap();
+        assert_eq!(
+            info.nixpkgs_revision,
+            "2222222222222222222222222222222222222222"
+        );
+        assert_eq!(info.nixos_release.as_deref(), Some("24.11"));
+        assert_eq!(info.last_modified, Some(1735000000));
+    }
+
+    #[test]
+    fn follows_input() {
+        let info = locked_revision(LOCK, "home-manager/nixpkgs").unwrap();
+        assert_eq!(
+            info.nixpkgs_revision,
+            "2222222222222222222222222222222222222222"
+        );
+    }
+
+    #[test]
+    fn missing_and_cyclic_inputs() {
+        assert!(locked_revision(LOCK, "nixpkgs-unstable").is_err());
+
+        let cycle = r#"{
+  "nodes": {
+    "a": { "inputs": { "nixpkgs": ["b", "nixpkgs"] } },
+    "b": { "inputs": { "nixpkgs": ["a", "nixpkgs"] } },
+    "root": { "inputs": { "a": "a", "b": "b" } }
+  },
+  "root": "root",
+  "version": 7
+}"#;
+        assert!(locked_revision(cycle, "a/nixpkgs").is_err());
+    }
+
+    #[test]
+    fn release_names() {
+        assert_eq!(release_from_ref("nixos-24.11").as_deref(), Some("24.11"));
+        assert_eq!(release_from_ref("release-24.05").as_deref(), Some("24.05"));
+        assert_eq!(release_from_ref("nixos-unstable"), None);
+        assert_eq!(release_from_ref("main"), None);
+    }
+}
@@ -0,0 +1,117 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use crate::Result;
+
+/// Advisory lock on a file, shared between processes and released on drop.
+///
+/// Lock files may be removed by whoever holds them exclusively, so after
+/// locking we check that the path still refers to the file we locked.
+#[derive(Debug)]
+pub(crate) struct FileLock {
+    _file: File,
+}
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Shared,
+    Exclusive,
+}
+
+impl FileLock {
+    /// Block until `path` is locked for exclusive use.
+    pub(crate) fn exclusive(path: &Path) -> Result<Self> {
+        Ok(Self::acquire(path, Mode::Exclusive, true)?.expect("blocking lock"))
+    }
+
+    /// Block until `path` is locked for shared use.
+    pub(crate) fn shared(path: &Path) -> Result<Self> {
+        Ok(Self::acquire(path, Mode::Shared, true)?.expect("blocking lock"))
+    }
+
+    /// Lock `path` for exclusive use, or `None` if someone else holds it.
+    pub(crate) fn try_exclusive(path: &Path) -> Result<Option<Self>> {
+        Self::acquire(path, Mode::Exclusive, false)
+    }
+
+    /// Lock `path` for shared use, or `None` if it is locked exclusively.
+    pub(crate) fn try_shared(path: &Path) -> Result<Option<Self>> {
+        Self::acquire(path, Mode::Shared, false)
+    }
+
+    /// Like [`FileLock::exclusive`], without blocking the async runtime.
+    pub(crate) async fn exclusive_async(path: PathBuf) -> Result<Self> {
+        tokio::task::spawn_blocking(move || Self::exclusive(&path))
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    /// Like [`FileLock::shared`], without blocking the async runtime.
+    pub(crate) async fn shared_async(path: PathBuf) -> Result<Self> {
+        tokio::task::spawn_blocking(move || Self::shared(&path))
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    fn acquire(path: &Path, mode: Mode, block: bool) -> Result<Option<Self>> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        loop {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?;
+            let locked = match (mode, block) {
+                (Mode::Shared, true) => file.lock_shared().map(|()| true),
+                (Mode::Exclusive, true) => file.lock().map(|()| true),
+                (Mode::Shared, false) => try_locked(file.try_lock_shared()),
+                (Mode::Exclusive, false) => try_locked(file.try_lock()),
+            }?;
+            if !locked {
+                return Ok(None);
+            }
+
+            let locked_meta = file.metadata()?;
+            match std::fs::metadata(path) {
+                Ok(m) if m.dev() == locked_meta.dev() && m.ino() == locked_meta.ino() => {
+                    return Ok(Some(Self { _file: file }));
+                }
+                // Removed or replaced while we were waiting, try again
+                _ => continue,
+            }
+        }
+    }
+}
+
+fn try_locked(result: std::result::Result<(), TryLockError>) -> io::Result<bool> {
+    match result {
+        Ok(()) => Ok(true),
+        Err(TryLockError::WouldBlock) => Ok(false),
+        Err(TryLockError::Error(e)) => Err(e),
+    }
+}
+
+/// Lock file guarding the database at `db_path`. Held shared while the
+/// database is open, and exclusively while it is downloaded or removed.
+pub(crate) fn db_lock_path(db_path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.lock", db_path.display()))
+}
+
+/// Write `contents` to a temporary file next to `path` and rename it into
+/// place, so readers never see a partially written file.
+pub(crate) async fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp = PathBuf::from(format!("{}.tmp.{}", path.display(), std::process::id()));
+    tokio::fs::write(&tmp, contents).await?;
+    if let Err(e) = tokio::fs::rename(&tmp, path).await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(e.into());
+    }
+    Ok(())
+}
@@ -1,23 +1,58 @@
+pub(crate) mod binaries;
+pub(crate) mod browse;
+pub mod cache;
 pub(crate) mod database;
+pub(crate) mod details;
+pub(crate) mod diff;
+pub mod fetch;
+pub(crate) mod flake_lock;
+pub(crate) mod lock;
+pub(crate) mod options;
+pub(crate) mod platform;
 pub(crate) mod revision;
 pub(crate) mod search;
+pub(crate) mod set;
+pub(crate) mod shared;
+pub(crate) mod signing;
 
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use crate::Result;
-use tracing::info;
+use crate::{Error, Result, Suggestion, SuggestionReason};
+use tracing::{info, warn};
 
-use search::{DbSearcher, SearchQuery, get_searcher_from_dir};
+use details::{parse_json_column, parse_licenses, parse_maintainers, parse_strings};
+use platform::parse_platforms;
+use search::{DbSearcher, get_searcher_from_dir};
 
-pub use search::{SearchResult, build_search_index_in_dir, index_dir_for_db_path};
+pub use binaries::Provider;
+pub use browse::{AttrChild, AttrChildKind, AttrChildren};
+pub use database::{DownloadProgress, ProgressSink};
+pub use details::{License, Maintainer, PkgDetails};
+pub use diff::RevisionDiff;
+pub use fetch::{ConnectOptions, Fetcher};
+pub use options::{ModuleInfo, OptionInfo, Scope};
+pub use platform::Platform;
+pub use revision::is_commit;
+pub use search::{
+    SearchFilters, SearchQuery, SearchResult, build_search_index_in_dir, index_dir_for_db_path,
+};
+pub use set::{MetadataSet, Sourced};
+pub use shared::SharedMetadata;
+pub use signing::{MANIFEST_EXTENSION, SIGNATURE_EXTENSION, checksum_manifest, sign_manifest};
+
+/// Suggestions attached to [`Error::PackageNotFound`].
+const MAX_SUGGESTIONS: usize = 5;
 
 /// Handle for querying Nix package metadata (SQLite + Tantivy search index).
 pub struct Metadata {
     conn: rusqlite::Connection,
-    searcher: DbSearcher,
+    searcher: Arc<DbSearcher>,
     db_path: PathBuf,
     nixpkgs_revision: Option<String>,
     nixos_release: Option<String>,
+    nixpkgs_last_modified: Option<u64>,
+    _lock: Option<Arc<lock::FileLock>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -31,12 +66,59 @@ pub enum AliasKind {
     },
 }
 
+impl AliasKind {
+    fn from_row(type_str: String, replacement: Option<String>, message: Option<String>) -> Self {
+        if type_str == "rename" {
+            AliasKind::Rename {
+                replacement: replacement.unwrap_or_default(),
+                message,
+            }
+        } else {
+            AliasKind::Removed {
+                message: message.unwrap_or_default(),
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AliasInfo {
     pub attribute: String,
     pub kind: AliasKind,
 }
 
+/// Outcome of [`Metadata::resolve`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// The attribute is a package in this database.
+    Found { attribute: String },
+    /// The attribute is an alias that renames, possibly over several hops,
+    /// to `attribute`. `chain` lists every alias that was followed.
+    Renamed {
+        from: String,
+        attribute: String,
+        chain: Vec<String>,
+        message: Option<String>,
+    },
+    /// The attribute (or an alias it renames to) was removed from nixpkgs.
+    Removed { attribute: String, message: String },
+}
+
+impl Resolution {
+    /// The package attribute to use, or [`Error::PackageRemoved`].
+    pub fn into_attribute(self) -> Result<String> {
+        match self {
+            Resolution::Found { attribute } | Resolution::Renamed { attribute, .. } => {
+                Ok(attribute)
+            }
+            Resolution::Removed { attribute, message } => Err(Error::PackageRemoved {
+                attr: attribute,
+                message,
+            }),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PkgInfo {
     pub attribute: String,
@@ -48,73 +130,173 @@ pub struct PkgInfo {
     pub unfree: bool,
 }
 
+/// In offline mode, fall back to the revision last recorded in the cache
+/// when the revision cannot be resolved.
+async fn revision_or_cached(
+    info: Result<revision::RevisionInfo>,
+    entry: database::DatabaseCacheEntry,
+) -> Result<revision::RevisionInfo> {
+    match info {
+        Err(err) if database::is_offline() => match database::cached_revision(entry).await {
+            Some(rev) => {
+                info!("Offline: using cached revision {}", rev);
+                Ok(revision::RevisionInfo {
+                    nixpkgs_revision: rev,
+                    nixos_release: None,
+                    last_modified: None,
+                })
+            }
+            None => Err(err),
+        },
+        info => info,
+    }
+}
+
 impl Metadata {
     /// Open from a `.db` file, building the search index if needed.
     pub fn open(db_path: &Path) -> Result<Self> {
+        // Best effort: keeps cache cleanup in other processes from removing
+        // the database while it is open, but read-only locations are fine too.
+        let db_lock = lock::FileLock::try_shared(&lock::db_lock_path(db_path))
+            .ok()
+            .flatten();
         let index_dir = index_dir_for_db_path(db_path);
         let conn = rusqlite::Connection::open(db_path)?;
 
-        if !index_dir.exists() {
-            info!("Building search index for {} ...", db_path.display());
-            build_search_index_in_dir(&conn, &index_dir)?;
-            info!("Search index written to {}", index_dir.display());
-        }
-
-        let searcher = match get_searcher_from_dir(&index_dir) {
+        let searcher = match search::open_index(db_path, &index_dir) {
             Ok(s) => s,
             Err(_) => {
-                // Index directory exists but is corrupt — rebuild
-                build_search_index_in_dir(&conn, &index_dir)?;
-                get_searcher_from_dir(&index_dir)?
+                // Only one process builds it, the others wait and use the result
+                let _index_lock =
+                    lock::FileLock::exclusive(&index_dir.with_extension("index.lock"))?;
+                match search::open_index(db_path, &index_dir) {
+                    Ok(s) => s,
+                    Err(reason) => {
+                        info!(
+                            "Building search index for {} ({}) ...",
+                            db_path.display(),
+                            reason
+                        );
+                        build_search_index_in_dir(&conn, &index_dir)?;
+                        info!("Search index written to {}", index_dir.display());
+                        get_searcher_from_dir(&index_dir)?
+                    }
+                }
             }
         };
 
         Ok(Self {
             conn,
-            searcher,
+            searcher: Arc::new(searcher),
             db_path: db_path.to_path_buf(),
             nixpkgs_revision: None,
             nixos_release: None,
+            nixpkgs_last_modified: None,
+            _lock: db_lock.map(Arc::new),
         })
     }
 
-    /// Connect to the current nixpkgs revision database.
+    /// Connect to the current nixpkgs revision database. When a flake is
+    /// configured this is the revision locked in its `flake.lock`, otherwise
+    /// that of the running system.
     pub async fn connect() -> Result<Self> {
-        let info = revision::get_revision().await?;
-        let path = database::fetch_database(
-            &info.nixpkgs_revision,
+        Self::connect_with(&ConnectOptions::default()).await
+    }
+
+    /// Like [`Metadata::connect`], reporting download progress to `progress`.
+    pub async fn connect_with_progress(progress: &dyn ProgressSink) -> Result<Self> {
+        Self::connect_with(&ConnectOptions {
+            progress,
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Like [`Metadata::connect`], with a custom [`Fetcher`] or progress sink.
+    pub async fn connect_with(options: &ConnectOptions<'_>) -> Result<Self> {
+        let info = revision_or_cached(
+            revision::get_revision(options.fetcher).await,
             database::DatabaseCacheEntry::Current,
         )
         .await?;
-        let mut md = Self::open(Path::new(&path))?;
-        md.nixpkgs_revision = Some(info.nixpkgs_revision);
-        md.nixos_release = info.nixos_release;
-        Ok(md)
+        Self::fetch_and_open(info, database::DatabaseCacheEntry::Current, options).await
     }
 
     /// Connect to the nixpkgs revision from the user's nix registry.
     pub async fn connect_registry() -> Result<Self> {
-        let info = revision::get_registry_revision().await?;
-        let path = database::fetch_database(
-            &info.nixpkgs_revision,
+        Self::connect_registry_with(&ConnectOptions::default()).await
+    }
+
+    /// Like [`Metadata::connect_registry`], reporting download progress to `progress`.
+    pub async fn connect_registry_with_progress(progress: &dyn ProgressSink) -> Result<Self> {
+        Self::connect_registry_with(&ConnectOptions {
+            progress,
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Like [`Metadata::connect_registry`], with a custom [`Fetcher`] or progress sink.
+    pub async fn connect_registry_with(options: &ConnectOptions<'_>) -> Result<Self> {
+        let info = revision_or_cached(
+            revision::get_registry_revision(options.fetcher).await,
             database::DatabaseCacheEntry::Current,
         )
         .await?;
-        let mut md = Self::open(Path::new(&path))?;
-        md.nixpkgs_revision = Some(info.nixpkgs_revision);
-        md.nixos_release = info.nixos_release;
-        Ok(md)
+        Self::fetch_and_open(info, database::DatabaseCacheEntry::Current, options).await
+    }
+
+    /// Connect to the database of the nix-channel `nix-env` installs from,
+    /// as found by [`nixenv::get_channel`](crate::nixenv::get_channel).
+    pub async fn connect_channel() -> Result<Self> {
+        Self::connect_channel_with(&ConnectOptions::default()).await
+    }
+
+    /// Like [`Metadata::connect_channel`], with a custom [`Fetcher`] or progress sink.
+    pub async fn connect_channel_with(options: &ConnectOptions<'_>) -> Result<Self> {
+        let info = revision_or_cached(
+            revision::get_nix_channel_revision(options.fetcher).await,
+            database::DatabaseCacheEntry::Current,
+        )
+        .await?;
+        Self::fetch_and_open(info, database::DatabaseCacheEntry::Current, options).await
     }
 
     /// Connect to the latest nixpkgs revision database.
     pub async fn connect_latest() -> Result<Self> {
-        let info = revision::get_latest_nixpkgs_revision().await?;
-        let path =
-            database::fetch_database(&info.nixpkgs_revision, database::DatabaseCacheEntry::New)
-                .await?;
+        Self::connect_latest_with(&ConnectOptions::default()).await
+    }
+
+    /// Like [`Metadata::connect_latest`], reporting download progress to `progress`.
+    pub async fn connect_latest_with_progress(progress: &dyn ProgressSink) -> Result<Self> {
+        Self::connect_latest_with(&ConnectOptions {
+            progress,
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Like [`Metadata::connect_latest`], with a custom [`Fetcher`] or progress sink.
+    pub async fn connect_latest_with(options: &ConnectOptions<'_>) -> Result<Self> {
+        let info = revision_or_cached(
+            revision::get_latest_nixpkgs_revision(options.fetcher).await,
+            database::DatabaseCacheEntry::New,
+        )
+        .await?;
+        Self::fetch_and_open(info, database::DatabaseCacheEntry::New, options).await
+    }
+
+    async fn fetch_and_open(
+        info: revision::RevisionInfo,
+        entry: database::DatabaseCacheEntry,
+        options: &ConnectOptions<'_>,
+    ) -> Result<Self> {
+        let (path, _lock) =
+            database::fetch_database(&info.nixpkgs_revision, entry, options).await?;
         let mut md = Self::open(Path::new(&path))?;
         md.nixpkgs_revision = Some(info.nixpkgs_revision);
         md.nixos_release = info.nixos_release;
+        md.nixpkgs_last_modified = info.last_modified;
         Ok(md)
     }
 
@@ -124,37 +306,125 @@ impl Metadata {
         limit: usize,
         score_threshold: f32,
     ) -> Result<Vec<SearchResult>> {
-        search::search(
-            &SearchQuery {
-                query,
-                limit,
-                score_threshold,
-            },
-            &self.searcher,
-        )
+        self.search_with(&SearchQuery {
+            query,
+            limit,
+            score_threshold,
+            ..Default::default()
+        })
     }
 
-    /// Look up a package by exact attribute name.
+    /// Search with a full [`SearchQuery`], including any [`SearchFilters`].
+    pub fn search_with(&self, query: &SearchQuery) -> Result<Vec<SearchResult>> {
+        search::search(query, &self.searcher)
+    }
+
+    /// Look up a package by exact attribute name. A miss is a
+    /// [`Error::PackageNotFound`] without suggestions; see [`Metadata::suggest`].
     pub fn get(&self, attribute: &str) -> Result<PkgInfo> {
         let mut stmt = self.conn.prepare_cached(
             "SELECT p.pname, p.version, m.description, m.broken, m.insecure, m.unfree \
              FROM pkgs p LEFT JOIN meta m ON p.attribute = m.attribute \
              WHERE p.attribute = ?",
         )?;
-        let result = stmt.query_row([attribute], |row| {
-            Ok(PkgInfo {
-                attribute: attribute.to_string(),
-                pname: row.get(0)?,
-                version: row.get(1)?,
-                description: row.get(2)?,
-                broken: row.get::<_, Option<i64>>(3)?.unwrap_or(0) != 0,
-                insecure: row.get::<_, Option<i64>>(4)?.unwrap_or(0) != 0,
-                unfree: row.get::<_, Option<i64>>(5)?.unwrap_or(0) != 0,
+        let result = stmt
+            .query_row([attribute], |row| {
+                Ok(PkgInfo {
+                    attribute: attribute.to_string(),
+                    pname: row.get(0)?,
+                    version: row.get(1)?,
+                    description: row.get(2)?,
+                    broken: row.get::<_, Option<i64>>(3)?.unwrap_or(0) != 0,
+                    insecure: row.get::<_, Option<i64>>(4)?.unwrap_or(0) != 0,
+                    unfree: row.get::<_, Option<i64>>(5)?.unwrap_or(0) != 0,
+                })
             })
-        })?;
+            .map_err(|e| lookup_error(attribute, e))?;
         Ok(result)
     }
 
+    /// Look up every stored `meta` column for a package by exact attribute name.
+    pub fn details(&self, attribute: &str) -> Result<PkgDetails> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT p.pname, p.version, m.description, m.long_description, \
+             m.homepage, m.download_page, m.changelog, m.license, m.maintainers, \
+             m.main_program, m.platforms, m.bad_platforms, \
+             m.broken, m.insecure, m.unfree \
+             FROM pkgs p LEFT JOIN meta m ON p.attribute = m.attribute \
+             WHERE p.attribute = ?",
+        )?;
+        let result = stmt
+            .query_row([attribute], |row| {
+                let text = |i: usize| -> rusqlite::Result<Option<String>> {
+                    Ok(row.get::<_, Option<String>>(i)?.filter(|s| !s.is_empty()))
+                };
+                let json = |i: usize| -> rusqlite::Result<Option<serde_json::Value>> {
+                    Ok(parse_json_column(
+                        row.get::<_, Option<String>>(i)?.as_deref(),
+                    ))
+                };
+                Ok(PkgDetails {
+                    attribute: attribute.to_string(),
+                    pname: row.get(0)?,
+                    version: row.get(1)?,
+                    description: text(2)?,
+                    long_description: text(3)?,
+                    homepage: json(4)?.map(|v| parse_strings(&v)).unwrap_or_default(),
+                    download_page: json(5)?.and_then(|v| parse_strings(&v).into_iter().next()),
+                    changelog: json(6)?.and_then(|v| parse_strings(&v).into_iter().next()),
+                    licenses: json(7)?.map(|v| parse_licenses(&v)).unwrap_or_default(),
+                    maintainers: json(8)?.map(|v| parse_maintainers(&v)).unwrap_or_default(),
+                    main_program: text(9)?,
+                    platforms: json(10)?.map(|v| parse_platforms(&v)).unwrap_or_default(),
+                    bad_platforms: json(11)?.map(|v| parse_platforms(&v)).unwrap_or_default(),
+                    broken: row.get::<_, Option<i64>>(12)?.unwrap_or(0) != 0,
+                    insecure: row.get::<_, Option<i64>>(13)?.unwrap_or(0) != 0,
+                    unfree: row.get::<_, Option<i64>>(14)?.unwrap_or(0) != 0,
+                })
+            })
+            .map_err(|e| lookup_error(attribute, e))?;
+        Ok(result)
+    }
+
+    /// Check whether a package can be built for `system` (e.g. `NIXARCH`),
+    /// based on its `meta.platforms` and `meta.badPlatforms`.
+    pub fn is_available_on(&self, attribute: &str, system: &str) -> Result<bool> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT m.platforms, m.bad_platforms \
+             FROM pkgs p LEFT JOIN meta m ON p.attribute = m.attribute \
+             WHERE p.attribute = ?",
+        )?;
+        let (platforms, bad_platforms) = stmt
+            .query_row([attribute], |row| {
+                Ok((
+                    row.get::<_, Option<String>>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                ))
+            })
+            .map_err(|e| lookup_error(attribute, e))?;
+        let platforms = parse_json_column(platforms.as_deref())
+            .map(|v| parse_platforms(&v))
+            .unwrap_or_default();
+        let bad_platforms = parse_json_column(bad_platforms.as_deref())
+            .map(|v| parse_platforms(&v))
+            .unwrap_or_default();
+        Ok(platform::available_on(&platforms, &bad_platforms, system))
+    }
+
+    /// Like [`Metadata::is_available_on`], but returns
+    /// [`Error::UnsupportedPlatform`] naming the supported platforms.
+    pub fn ensure_available_on(&self, attribute: &str, system: &str) -> Result<()> {
+        if self.is_available_on(attribute, system)? {
+            return Ok(());
+        }
+        let details = self.details(attribute)?;
+        Err(Error::UnsupportedPlatform {
+            attr: attribute.to_string(),
+            system: system.trim().to_string(),
+            platforms: details.platforms.iter().map(|p| p.to_string()).collect(),
+        })
+    }
+
     /// Look up packages by pname.
     pub fn get_by_pname(&self, pname: &str) -> Result<Vec<PkgInfo>> {
         let mut stmt = self.conn.prepare_cached(
@@ -180,6 +450,20 @@ impl Metadata {
         Ok(results)
     }
 
+    /// Complete an attribute path one attribute set at a time, e.g.
+    /// `python3Packages.req` gives `python3Packages.requests`, and `python3Pa`
+    /// gives `python3Packages.`. Shallower and shorter completions come first.
+    pub fn complete(&self, prefix: &str, limit: usize) -> Result<Vec<String>> {
+        browse::complete(&self.conn, prefix, limit)
+    }
+
+    /// List the packages and attribute sets directly below `prefix` (e.g.
+    /// `python3Packages`, or `""` for the top level), `limit` at a time
+    /// starting at `offset`.
+    pub fn children(&self, prefix: &str, offset: usize, limit: usize) -> Result<AttrChildren> {
+        browse::children(&self.conn, prefix, offset, limit)
+    }
+
     /// Return all attribute names that have a NixOS `programs.<name>.enable` option.
     pub fn all_program_option_attrs(&self) -> Vec<String> {
         self.conn
@@ -220,6 +504,38 @@ impl Metadata {
             .unwrap_or(false)
     }
 
+    /// The `programs.<attribute>.*` options of a NixOS or home-manager
+    /// program module, sorted by name. Empty if there is no such module.
+    pub fn program_options(&self, attribute: &str, scope: Scope) -> Result<Vec<OptionInfo>> {
+        options::program_options(&self.conn, attribute, scope)
+    }
+
+    /// Packages that provide `command`, most likely candidates first: e.g.
+    /// `rg` gives `ripgrep`. Useful for command-not-found handlers.
+    pub fn providers_of(&self, command: &str) -> Result<Vec<Provider>> {
+        binaries::providers_of(&self.conn, command)
+    }
+
+    /// Look up an enable-able NixOS module by its option path, e.g. `services.tailscale`.
+    pub fn module(&self, path: &str) -> Option<ModuleInfo> {
+        options::module(&self.conn, path)
+    }
+
+    /// NixOS modules (outside of `programs`) that install `attribute`.
+    pub fn modules_for(&self, attribute: &str) -> Vec<ModuleInfo> {
+        options::modules_for(&self.conn, attribute)
+    }
+
+    /// All enable-able NixOS modules in the database, sorted by path.
+    pub fn all_modules(&self) -> Vec<ModuleInfo> {
+        options::all_modules(&self.conn)
+    }
+
+    /// The options of a NixOS module, sorted by name. Empty if there is no such module.
+    pub fn module_options(&self, path: &str) -> Result<Vec<OptionInfo>> {
+        options::module_options(&self.conn, path)
+    }
+
     /// Look up an alias by the deprecated attribute name.
     pub fn get_alias(&self, attribute: &str) -> Option<AliasInfo> {
         let mut stmt = self
@@ -227,27 +543,144 @@ impl Metadata {
             .prepare_cached("SELECT type, replacement, message FROM aliases WHERE alias = ?")
             .ok()?;
         stmt.query_row([attribute], |row| {
-            let type_str: String = row.get(0)?;
-            let replacement: Option<String> = row.get(1)?;
-            let message: Option<String> = row.get(2)?;
-            let kind = if type_str == "rename" {
-                AliasKind::Rename {
-                    replacement: replacement.unwrap_or_default(),
-                    message,
-                }
-            } else {
-                AliasKind::Removed {
-                    message: message.unwrap_or_default(),
-                }
-            };
             Ok(AliasInfo {
                 attribute: attribute.to_string(),
-                kind,
+                kind: AliasKind::from_row(row.get(0)?, row.get(1)?, row.get(2)?),
             })
         })
         .ok()
     }
 
+    /// Resolve an attribute to a package, following `Rename` aliases until a
+    /// real package is reached.
+    pub fn resolve(&self, attribute: &str) -> Result<Resolution> {
+        let mut current = attribute.to_string();
+        let mut chain: Vec<String> = Vec::new();
+        let mut message = None;
+
+        loop {
+            if self.has_package(&current)? {
+                return Ok(if chain.is_empty() {
+                    Resolution::Found { attribute: current }
+                } else {
+                    Resolution::Renamed {
+                        from: attribute.to_string(),
+                        attribute: current,
+                        chain,
+                        message,
+                    }
+                });
+            }
+
+            match self.get_alias(&current).map(|a| a.kind) {
+                Some(AliasKind::Rename {
+                    replacement,
+                    message: m,
+                }) => {
+                    chain.push(current);
+                    if chain.contains(&replacement) {
+                        chain.push(replacement);
+                        return Err(Error::InvalidDatabase {
+                            reason: format!("alias cycle: {}", chain.join(" -> ")),
+                        });
+                    }
+                    message = m.or(message);
+                    current = replacement;
+                }
+                Some(AliasKind::Removed { message }) => {
+                    return Ok(Resolution::Removed {
+                        attribute: current,
+                        message,
+                    });
+                }
+                None => return Err(not_found(attribute)),
+            }
+        }
+    }
+
+    /// Resolve an attribute for installation. Renames are followed and logged;
+    /// removed and unknown packages are an error, the latter with suggestions.
+    pub(crate) fn resolve_installable(&self, attribute: &str) -> Result<String> {
+        match self.resolve(attribute) {
+            Ok(Resolution::Renamed {
+                attribute: target,
+                message,
+                ..
+            }) => {
+                warn!(
+                    "{} has been renamed to {}{}",
+                    attribute,
+                    target,
+                    message.map(|m| format!(": {}", m)).unwrap_or_default()
+                );
+                Ok(target)
+            }
+            Ok(res) => res.into_attribute(),
+            Err(Error::PackageNotFound { attr, .. }) => Err(Error::PackageNotFound {
+                suggestions: self.suggest(&attr, MAX_SUGGESTIONS),
+                attr,
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Attributes the user may have meant instead of `attribute`, best first:
+    /// aliases and case-insensitive matches, packages with that `pname`, then
+    /// close spellings.
+    pub fn suggest(&self, attribute: &str, limit: usize) -> Vec<Suggestion> {
+        let mut suggestions: Vec<Suggestion> = Vec::new();
+        let mut add = |attr: String, reason: SuggestionReason| {
+            if attr != attribute && !suggestions.iter().any(|s| s.attribute == attr) {
+                suggestions.push(Suggestion {
+                    attribute: attr,
+                    reason,
+                });
+            }
+        };
+
+        let case_insensitive = self
+            .conn
+            .prepare_cached(
+                "SELECT attribute FROM pkgs WHERE attribute = ?1 COLLATE NOCASE \
+                 UNION ALL \
+                 SELECT replacement FROM aliases \
+                 WHERE alias = ?1 COLLATE NOCASE AND replacement IS NOT NULL",
+            )
+            .and_then(|mut stmt| {
+                stmt.query_map([attribute], |row| row.get::<_, String>(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .unwrap_or_default();
+        for attr in case_insensitive {
+            add(attr, SuggestionReason::Alias);
+        }
+
+        let name = attribute.rsplit('.').next().unwrap_or(attribute);
+        for pkg in self.get_by_pname(name).unwrap_or_default() {
+            add(pkg.attribute, SuggestionReason::Pname);
+        }
+
+        for attr in search::similar_attributes(attribute, limit, &self.searcher).unwrap_or_default()
+        {
+            add(attr, SuggestionReason::Similar);
+        }
+
+        suggestions.truncate(limit);
+        suggestions
+    }
+
+    fn has_package(&self, attribute: &str) -> Result<bool> {
+        Ok(self
+            .conn
+            .prepare_cached("SELECT 1 FROM pkgs WHERE attribute = ?")?
+            .exists([attribute])?)
+    }
+
+    /// Compare this database with `other`, treating `other` as the newer revision.
+    pub fn diff(&self, other: &Metadata) -> Result<RevisionDiff> {
+        diff::diff(&self.conn, &other.db_path)
+    }
+
     pub fn nixpkgs_revision(&self) -> Option<&str> {
         self.nixpkgs_revision.as_deref()
     }
@@ -256,6 +689,12 @@ impl Metadata {
         self.nixos_release.as_deref()
     }
 
+    /// Commit time of [`Metadata::nixpkgs_revision`] in seconds since the
+    /// epoch, when it was read from a `flake.lock`.
+    pub fn nixpkgs_last_modified(&self) -> Option<u64> {
+        self.nixpkgs_last_modified
+    }
+
     pub fn db_path(&self) -> &Path {
         &self.db_path
     }
@@ -264,3 +703,19 @@ impl Metadata {
         &self.conn
     }
 }
+
+fn lookup_error(attribute: &str, err: rusqlite::Error) -> Error {
+    match err {
+        rusqlite::Error::QueryReturnedNoRows => not_found(attribute),
+        e => e.into(),
+    }
+}
+
+/// [`Error::PackageNotFound`] for `attribute`. Suggestions are left out, as
+/// lookups are often just existence checks; user-facing paths add them.
+fn not_found(attribute: &str) -> Error {
+    Error::PackageNotFound {
+        attr: attribute.to_string(),
+        suggestions: Vec::new(),
+    }
+}
@@ -0,0 +1,161 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::Result;
+
+/// Which module system an option belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    NixOs,
+    HomeManager,
+}
+
+impl Scope {
+    fn table(self) -> &'static str {
+        match self {
+            Scope::NixOs => "program_options",
+            Scope::HomeManager => "hm_program_options",
+        }
+    }
+}
+
+/// A single module option, as described by the NixOS or home-manager manual.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionInfo {
+    /// Full option path, e.g. `programs.git.enable`.
+    pub name: String,
+    /// Human readable type, e.g. `boolean` or `null or string`.
+    pub option_type: String,
+    /// Default value as a Nix expression.
+    pub default: Option<String>,
+    /// Example value as a Nix expression.
+    pub example: Option<String>,
+    pub description: Option<String>,
+    pub read_only: bool,
+}
+
+#[derive(Deserialize)]
+struct OptionJson {
+    #[serde(rename = "type", default)]
+    option_type: String,
+    default: Option<Value>,
+    example: Option<Value>,
+    description: Option<Value>,
+    #[serde(rename = "readOnly", default)]
+    read_only: bool,
+}
+
+/// Render a `default`/`example` value. The manual stores these either as
+/// `{ _type = "literalExpression"; text = ...; }` or as plain JSON.
+fn render_value(value: Value) -> String {
+    match value {
+        Value::Object(ref obj) if obj.contains_key("_type") => obj
+            .get("text")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| value.to_string()),
+        Value::String(s) => format!(
+            "\"{}\"",
+            s.replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace("${", "\\${")
+                .replace('\n', "\\n")
+        ),
+        v => v.to_string(),
+    }
+}
+
+/// Descriptions are plain strings in newer manuals and `mdDoc` objects in older ones.
+fn render_description(value: Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s),
+        Value::Object(obj) => obj.get("text").and_then(Value::as_str).map(str::to_string),
+        _ => None,
+    }
+    .filter(|s| !s.is_empty())
+}
+
+pub(crate) fn program_options(
+    conn: &rusqlite::Connection,
+    attribute: &str,
+    scope: Scope,
+) -> Result<Vec<OptionInfo>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT options FROM {} WHERE attribute = ?",
+        scope.table()
+    ))?;
+    let json: Option<String> = stmt
+        .query_map([attribute], |row| row.get(0))?
+        .next()
+        .transpose()?;
+    match json {
+        Some(json) => parse_options(&json),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// An enable-able NixOS module outside of `programs`, e.g. `services.tailscale`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleInfo {
+    /// Option path of the module; `<path>.enable` turns it on.
+    pub path: String,
+    /// The package the module installs, if known.
+    pub attribute: Option<String>,
+}
+
+fn module_from_row(row: &rusqlite::Row) -> rusqlite::Result<ModuleInfo> {
+    Ok(ModuleInfo {
+        path: row.get(0)?,
+        attribute: row.get(1)?,
+    })
+}
+
+// Databases generated before the modules table existed have no modules, so
+// query errors are treated as empty results.
+
+pub(crate) fn module(conn: &rusqlite::Connection, path: &str) -> Option<ModuleInfo> {
+    conn.prepare_cached("SELECT path, attribute FROM modules WHERE path = ?")
+        .and_then(|mut stmt| stmt.query_row([path], module_from_row))
+        .ok()
+}
+
+pub(crate) fn modules_for(conn: &rusqlite::Connection, attribute: &str) -> Vec<ModuleInfo> {
+    conn.prepare_cached("SELECT path, attribute FROM modules WHERE attribute = ? ORDER BY path")
+        .and_then(|mut stmt| stmt.query_map([attribute], module_from_row)?.collect())
+        .unwrap_or_default()
+}
+
+pub(crate) fn all_modules(conn: &rusqlite::Connection) -> Vec<ModuleInfo> {
+    conn.prepare_cached("SELECT path, attribute FROM modules ORDER BY path")
+        .and_then(|mut stmt| stmt.query_map([], module_from_row)?.collect())
+        .unwrap_or_default()
+}
+
+pub(crate) fn module_options(conn: &rusqlite::Connection, path: &str) -> Result<Vec<OptionInfo>> {
+    let json: Option<String> = conn
+        .prepare_cached("SELECT options FROM modules WHERE path = ?")
+        .and_then(|mut stmt| stmt.query_map([path], |row| row.get(0))?.next().transpose())
+        .unwrap_or_default();
+    match json {
+        Some(json) => parse_options(&json),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn parse_options(json: &str) -> Result<Vec<OptionInfo>> {
+    let raw: HashMap<String, OptionJson> = serde_json::from_str(json)?;
+    let mut options: Vec<OptionInfo> = raw
+        .into_iter()
+        .map(|(name, opt)| OptionInfo {
+            name,
+            option_type: opt.option_type,
+            default: opt.default.map(render_value),
+            example: opt.example.map(render_value),
+            description: opt.description.and_then(render_description),
+            read_only: opt.read_only,
+        })
+        .collect();
+    options.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(options)
+}
@@ -0,0 +1,174 @@
+use serde_json::Value;
+
+/// Entry of `meta.platforms` or `meta.badPlatforms`.
+///
+/// nixpkgs emits either a system double such as `x86_64-linux` or an
+/// attrset pattern from `lib.systems.inspect.patterns` that is matched
+/// against the parsed system, e.g. `{ "kernel": { "name": "linux" } }`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Platform {
+    System(String),
+    Pattern(Value),
+}
+
+impl std::fmt::Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Platform::System(s) => write!(f, "{}", s),
+            Platform::Pattern(p) => write!(f, "{}", p),
+        }
+    }
+}
+
+impl Platform {
+    /// Whether this entry matches `system`. Returns `None` when the entry
+    /// is a pattern that uses attributes we cannot derive from a system double.
+    pub fn matches(&self, system: &str) -> Option<bool> {
+        match self {
+            Platform::System(s) => Some(s == system.trim()),
+            Platform::Pattern(p) => match_attrs(p, &parse_system(system.trim()), false),
+        }
+    }
+}
+
+/// Systems that patterns are expanded against when building the search index.
+pub(crate) const KNOWN_SYSTEMS: &[&str] = &[
+    "x86_64-linux",
+    "aarch64-linux",
+    "i686-linux",
+    "armv6l-linux",
+    "armv7l-linux",
+    "riscv64-linux",
+    "powerpc64le-linux",
+    "loongarch64-linux",
+    "x86_64-darwin",
+    "aarch64-darwin",
+    "x86_64-freebsd",
+    "aarch64-freebsd",
+    "x86_64-netbsd",
+    "x86_64-openbsd",
+    "x86_64-windows",
+    "i686-windows",
+    "wasm32-wasi",
+];
+
+pub(crate) fn parse_platforms(value: &Value) -> Vec<Platform> {
+    let entry = |v: &Value| match v {
+        Value::String(s) => Some(Platform::System(s.clone())),
+        Value::Object(_) => Some(Platform::Pattern(v.clone())),
+        _ => None,
+    };
+    match value {
+        Value::Array(arr) => arr.iter().filter_map(entry).collect(),
+        v => entry(v).into_iter().collect(),
+    }
+}
+
+/// Mirror of nixpkgs' `lib.meta.availableOn`: a package without
+/// `meta.platforms` is available everywhere, and `meta.badPlatforms` wins.
+pub(crate) fn available_on(
+    platforms: &[Platform],
+    bad_platforms: &[Platform],
+    system: &str,
+) -> bool {
+    // Patterns we cannot evaluate are given the benefit of the doubt, so an
+    // unknown attribute never blocks an install on its own.
+    let supported =
+        platforms.is_empty() || platforms.iter().any(|p| p.matches(system).unwrap_or(true));
+    let excluded = bad_platforms
+        .iter()
+        .any(|p| p.matches(system).unwrap_or(false));
+    supported && !excluded
+}
+
+/// Subset of `lib.systems.parse.mkSystemFromString` that patterns match against.
+fn parse_system(system: &str) -> Value {
+    let (cpu, kernel) = system.split_once('-').unwrap_or((system, ""));
+
+    let (family, bits, big_endian) = match cpu {
+        "x86_64" => ("x86", 64, false),
+        "i686" | "i586" | "i486" | "i386" => ("x86", 32, false),
+        "aarch64" => ("arm", 64, false),
+        "aarch64_be" => ("arm", 64, true),
+        c if c.starts_with("arm") => ("arm", 32, false),
+        "riscv64" => ("riscv", 64, false),
+        "riscv32" => ("riscv", 32, false),
+        "powerpc64le" => ("power", 64, false),
+        "powerpc64" => ("power", 64, true),
+        "powerpc" => ("power", 32, true),
+        "mips64el" => ("mips", 64, false),
+        "mipsel" => ("mips", 32, false),
+        "mips64" => ("mips", 64, true),
+        "mips" => ("mips", 32, true),
+        "loongarch64" => ("loongarch", 64, false),
+        "s390x" => ("s390", 64, true),
+        "wasm32" => ("wasm", 32, false),
+        "wasm64" => ("wasm", 64, false),
+        _ => ("", 0, false),
+    };
+
+    let (exec_format, families): (&str, &[&str]) = match kernel {
+        "linux" | "freebsd" | "netbsd" | "openbsd" | "none" => (
+            "elf",
+            if kernel.ends_with("bsd") {
+                &["bsd"]
+            } else {
+                &[]
+            },
+        ),
+        "darwin" => ("macho", &["darwin"]),
+        "windows" | "cygwin" => ("pe", &[]),
+        "wasi" => ("wasm", &[]),
+        _ => ("unknown", &[]),
+    };
+
+    let mut cpu_json = serde_json::json!({
+        "name": cpu,
+        "significantByte": {
+            "name": if big_endian { "bigEndian" } else { "littleEndian" },
+        },
+    });
+    if !family.is_empty() {
+        cpu_json["family"] = family.into();
+        cpu_json["bits"] = bits.into();
+    }
+
+    let families_json: serde_json::Map<String, Value> = families
+        .iter()
+        .map(|f| (f.to_string(), serde_json::json!({ "name": f })))
+        .collect();
+
+    serde_json::json!({
+        "cpu": cpu_json,
+        "kernel": {
+            "name": kernel,
+            "execFormat": { "name": exec_format },
+            "families": families_json,
+        },
+    })
+}
+
+/// `lib.matchAttrs`: every attribute in `pattern` must be present and equal
+/// in `parsed`. Attributes we do not model yield `None`, except inside
+/// `kernel.families`, which is complete for the kernels we know about.
+fn match_attrs(pattern: &Value, parsed: &Value, complete: bool) -> Option<bool> {
+    match (pattern, parsed) {
+        (Value::Object(pat), Value::Object(obj)) => {
+            let mut result = Some(true);
+            for (key, pv) in pat {
+                match obj.get(key) {
+                    Some(ov) => match match_attrs(pv, ov, key == "families") {
+                        Some(true) => {}
+                        Some(false) => return Some(false),
+                        None => result = None,
+                    },
+                    None if complete => return Some(false),
+                    None => result = None,
+                }
+            }
+            result
+        }
+        (Value::Object(_), _) => None,
+        (p, o) => Some(p == o),
+    }
+}
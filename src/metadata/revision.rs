@@ -1,8 +1,23 @@
 use crate::{Error, Result};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 use tokio::process::Command;
+use tracing::debug;
 
-use crate::IS_NIXOS;
+use super::database::is_offline;
+use super::fetch::{self, FetchRequest, Fetcher};
+use super::flake_lock::{DEFAULT_NIXPKGS_INPUT, read_locked_revision, release_from_ref};
+use super::lock::write_atomic;
+use crate::{CACHEDIR, HOME, IS_NIXOS, config::configfile};
+
+const DEFAULT_GITHUB_API_URL: &str = "https://api.github.com";
+const DEFAULT_CHANNELS_URL: &str = "https://channels.nixos.org";
+/// Seconds a resolved channel or branch revision is reused by default.
+const DEFAULT_REVISION_CACHE_TTL: u64 = 3600;
 
 #[derive(Debug, Deserialize)]
 struct NixosVersionJson {
@@ -21,76 +36,522 @@ struct GhResponse {
 pub(crate) struct RevisionInfo {
     pub nixpkgs_revision: String,
     pub nixos_release: Option<String>,
+    /// Commit time of the revision in seconds since the epoch, when known.
+    pub last_modified: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct CachedLookup {
+    rev: String,
+    /// Seconds since the epoch.
+    resolved_at: u64,
 }
 
-pub(crate) async fn get_revision() -> Result<RevisionInfo> {
+fn revision_cache_path() -> PathBuf {
+    Path::new(&*CACHEDIR).join("revisions.json")
+}
+
+/// Run `lookup` to resolve a moving reference such as a channel or branch to
+/// a commit, reusing the result for
+/// [`revision_cache_ttl`](crate::config::configfile::LibSnowConfig::revision_cache_ttl) seconds.
+async fn cached_lookup(key: &str, lookup: impl Future<Output = Result<String>>) -> Result<String> {
+    let ttl = configfile::get_config()
+        .ok()
+        .and_then(|c| c.revision_cache_ttl)
+        .unwrap_or(DEFAULT_REVISION_CACHE_TTL);
+    if ttl == 0 {
+        return lookup.await;
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let fresh = |entry: &CachedLookup| now.saturating_sub(entry.resolved_at) < ttl;
+
+    let mut entries: HashMap<String, CachedLookup> =
+        match tokio::fs::read_to_string(revision_cache_path()).await {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+            Err(_) => HashMap::new(),
+        };
+    if let Some(entry) = entries.get(key).filter(|e| fresh(e)) {
+        debug!("Using cached revision {} for {}", entry.rev, key);
+        return Ok(entry.rev.clone());
+    }
+
+    let rev = lookup.await?;
+    entries.retain(|_, e| fresh(e));
+    entries.insert(
+        key.to_string(),
+        CachedLookup {
+            rev: rev.clone(),
+            resolved_at: now,
+        },
+    );
+    // The cache is only an optimization
+    let written = match serde_json::to_vec(&entries) {
+        Ok(json) => match tokio::fs::create_dir_all(&*CACHEDIR).await {
+            Ok(()) => write_atomic(&revision_cache_path(), &json).await,
+            Err(e) => Err(e.into()),
+        },
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = written {
+        debug!("Failed to write revision cache: {}", e);
+    }
+    Ok(rev)
+}
+
+fn ensure_online(what: &str) -> Result<()> {
+    if is_offline() {
+        return Err(Error::Config {
+            reason: format!("offline mode: cannot resolve {} without network", what),
+        });
+    }
+    Ok(())
+}
+
+/// The revision the next rebuild will use: the nixpkgs input locked in the
+/// configured flake's `flake.lock`, or else the running system's revision.
+pub(crate) async fn get_revision(fetcher: &dyn Fetcher) -> Result<RevisionInfo> {
+    let config = configfile::get_config().unwrap_or_default();
+    if let Ok(flake_dir) = config.get_flake_dir() {
+        let input = config
+            .flake_nixpkgs_input
+            .as_deref()
+            .unwrap_or(DEFAULT_NIXPKGS_INPUT);
+        match read_locked_revision(Path::new(&flake_dir), input).await? {
+            Some(info) => return Ok(info),
+            None => debug!("No flake.lock in {}, using system revision", flake_dir),
+        }
+    }
+    get_system_revision(fetcher).await
+}
+
+/// The running NixOS system's revision, from `nixos-version --json`.
+async fn nixos_version_revision() -> Result<RevisionInfo> {
+    let output = Command::new("nixos-version").arg("--json").output().await?;
+    let output = String::from_utf8(output.stdout)?;
+    let version: NixosVersionJson = serde_json::from_str(&output)?;
+    // 24.11.12345678.abcdefg -> 24.11
+    let release = version
+        .nixos_version
+        .split('.')
+        .take(2)
+        .collect::<Vec<_>>()
+        .join(".");
+    Ok(RevisionInfo {
+        nixpkgs_revision: version.nixpkgs_revision,
+        nixos_release: Some(release),
+        last_modified: None,
+    })
+}
+
+async fn get_system_revision(fetcher: &dyn Fetcher) -> Result<RevisionInfo> {
     if *IS_NIXOS {
-        let output = Command::new("nixos-version").arg("--json").output().await?;
-        let output = String::from_utf8(output.stdout)?;
-        let version: NixosVersionJson = serde_json::from_str(&output)?;
-        // 24.11.12345678.abcdefg -> 24.11
-        let release = version
-            .nixos_version
-            .split('.')
-            .take(2)
-            .collect::<Vec<_>>()
-            .join(".");
-        Ok(RevisionInfo {
-            nixpkgs_revision: version.nixpkgs_revision,
-            nixos_release: Some(release),
+        return nixos_version_revision().await;
+    }
+    let output = Command::new("nix")
+        .arg("registry")
+        .arg("list")
+        .output()
+        .await?;
+    let output = String::from_utf8(output.stdout)?;
+    let url = registry_nixpkgs_url(&output)?;
+    resolve_flake_ref(fetcher, &FlakeRef::parse(url)?).await
+}
+
+/// The target of the global `nixpkgs` entry in `nix registry list` output.
+fn registry_nixpkgs_url(output: &str) -> Result<&str> {
+    output
+        .lines()
+        .find(|x| x.contains("global flake:nixpkgs"))
+        .ok_or_else(|| Error::NixRegistry {
+            reason: "no nixpkgs flake found".into(),
+        })?
+        .split_whitespace()
+        .nth(2)
+        .ok_or_else(|| Error::NixRegistry {
+            reason: "invalid registry entry".into(),
         })
-    } else {
-        let output = Command::new("nix")
-            .arg("registry")
-            .arg("list")
-            .output()
+}
+
+/// Code forges with a `<forge>:owner/repo` shorthand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Forge {
+    GitHub,
+    GitLab,
+    SourceHut,
+}
+
+/// A flake reference, as found in the nix registry. See `nix help flake`
+/// for the syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum FlakeRef {
+    /// `github:NixOS/nixpkgs/nixos-24.11`, also `gitlab:` and `sourcehut:`.
+    Forge {
+        forge: Forge,
+        owner: String,
+        repo: String,
+        host: Option<String>,
+        git_ref: Option<String>,
+        rev: Option<String>,
+    },
+    /// `git+https://github.com/NixOS/nixpkgs?ref=nixos-24.11`, or any other
+    /// `git+` scheme.
+    Git {
+        url: String,
+        git_ref: Option<String>,
+        rev: Option<String>,
+    },
+    /// A tarball such as a channel's `nixexprs.tar.xz` or a FlakeHub URL.
+    Tarball { url: String },
+    /// `path:/nix/store/...-source` or a plain absolute path.
+    Path { path: String, rev: Option<String> },
+    /// Another registry entry, e.g. `flake:nixpkgs/nixos-24.11`.
+    Indirect {
+        id: String,
+        git_ref: Option<String>,
+        rev: Option<String>,
+    },
+}
+
+fn invalid_flake_ref(url: &str) -> Error {
+    Error::NixRegistry {
+        reason: format!("unsupported nixpkgs registry entry: {}", url),
+    }
+}
+
+/// Whether `s` is a full git commit hash, as databases are named by.
+pub fn is_commit(s: &str) -> bool {
+    s.len() == 40 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Decode the `%XX` escapes nix uses in flake reference query parameters.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(hex) = s.get(i + 1..i + 3)
+            && let Ok(b) = u8::from_str_radix(hex, 16)
+        {
+            out.push(b);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Split `ref-or-rev` path segments of shorthand forms into a ref and a rev.
+fn ref_or_rev(segment: Option<&str>) -> (Option<String>, Option<String>) {
+    match segment.filter(|s| !s.is_empty()) {
+        Some(s) if is_commit(s) => (None, Some(s.to_string())),
+        Some(s) => (Some(s.to_string()), None),
+        None => (None, None),
+    }
+}
+
+impl FlakeRef {
+    pub(crate) fn parse(url: &str) -> Result<Self> {
+        let (base, query) = url.split_once('?').unwrap_or((url, ""));
+        let params: Vec<(&str, String)> = query
+            .split('&')
+            .filter_map(|p| p.split_once('='))
+            .map(|(k, v)| (k, percent_decode(v)))
+            .collect();
+        let param = |key: &str| {
+            params
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.clone())
+        };
+
+        let (scheme, rest) = match base.split_once(':') {
+            Some((scheme, rest)) => (scheme, rest),
+            None if base.starts_with('/') => ("path", base),
+            // Bare registry names such as `nixpkgs`
+            None => ("flake", base),
+        };
+
+        let flake_ref = match scheme {
+            "github" | "gitlab" | "sourcehut" => {
+                let forge = match scheme {
+                    "github" => Forge::GitHub,
+                    "gitlab" => Forge::GitLab,
+                    _ => Forge::SourceHut,
+                };
+                let mut segments = rest.splitn(3, '/');
+                let (Some(owner), Some(repo)) = (segments.next(), segments.next()) else {
+                    return Err(invalid_flake_ref(url));
+                };
+                let (git_ref, rev) = ref_or_rev(segments.next());
+                FlakeRef::Forge {
+                    forge,
+                    owner: owner.to_string(),
+                    repo: repo.to_string(),
+                    host: param("host"),
+                    git_ref: git_ref.or_else(|| param("ref")),
+                    rev: rev.or_else(|| param("rev")),
+                }
+            }
+            s if s.starts_with("git+") => FlakeRef::Git {
+                url: base.trim_start_matches("git+").to_string(),
+                git_ref: param("ref"),
+                rev: param("rev"),
+            },
+            "tarball+http" | "tarball+https" | "tarball+file" | "file+http" | "file+https" => {
+                FlakeRef::Tarball {
+                    url: url.split_once('+').map_or(url, |(_, u)| u).to_string(),
+                }
+            }
+            "http" | "https" => FlakeRef::Tarball {
+                url: url.to_string(),
+            },
+            "path" => FlakeRef::Path {
+                path: rest.to_string(),
+                rev: param("rev"),
+            },
+            "flake" => {
+                let (id, tail) = rest.split_once('/').unwrap_or((rest, ""));
+                if id.is_empty() {
+                    return Err(invalid_flake_ref(url));
+                }
+                // `id/ref/rev` or `id/ref-or-rev`
+                let (git_ref, rev) = match tail.split_once('/') {
+                    Some((git_ref, rev)) => (Some(git_ref.to_string()), Some(rev.to_string())),
+                    None => ref_or_rev(Some(tail)),
+                };
+                FlakeRef::Indirect {
+                    id: id.to_string(),
+                    git_ref: git_ref.or_else(|| param("ref")),
+                    rev: rev.or_else(|| param("rev")),
+                }
+            }
+            _ => return Err(invalid_flake_ref(url)),
+        };
+        Ok(flake_ref)
+    }
+
+    /// The commit, if the reference pins one.
+    fn rev(&self) -> Option<&str> {
+        match self {
+            FlakeRef::Forge { rev, .. }
+            | FlakeRef::Git { rev, .. }
+            | FlakeRef::Path { rev, .. }
+            | FlakeRef::Indirect { rev, .. } => rev.as_deref(),
+            FlakeRef::Tarball { .. } => None,
+        }
+    }
+
+    fn git_ref(&self) -> Option<&str> {
+        match self {
+            FlakeRef::Forge { git_ref, .. }
+            | FlakeRef::Git { git_ref, .. }
+            | FlakeRef::Indirect { git_ref, .. } => git_ref.as_deref(),
+            FlakeRef::Tarball { .. } | FlakeRef::Path { .. } => None,
+        }
+    }
+
+    /// The flake reference in nix's URL-like syntax.
+    fn to_url(&self) -> String {
+        let mut params = Vec::new();
+        let base = match self {
+            FlakeRef::Forge {
+                forge,
+                owner,
+                repo,
+                host,
+                ..
+            } => {
+                if let Some(host) = host {
+                    params.push(format!("host={}", host));
+                }
+                let scheme = match forge {
+                    Forge::GitHub => "github",
+                    Forge::GitLab => "gitlab",
+                    Forge::SourceHut => "sourcehut",
+                };
+                format!("{}:{}/{}", scheme, owner, repo)
+            }
+            FlakeRef::Git { url, .. } => format!("git+{}", url),
+            FlakeRef::Tarball { url } => return url.clone(),
+            FlakeRef::Path { path, .. } => format!("path:{}", path),
+            FlakeRef::Indirect { id, .. } => format!("flake:{}", id),
+        };
+        if let Some(git_ref) = self.git_ref() {
+            params.push(format!("ref={}", git_ref));
+        }
+        if let Some(rev) = self.rev() {
+            params.push(format!("rev={}", rev));
+        }
+        if params.is_empty() {
+            base
+        } else {
+            format!("{}?{}", base, params.join("&"))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FlakeMetadataJson {
+    revision: Option<String>,
+    #[serde(rename = "lastModified")]
+    last_modified: Option<u64>,
+}
+
+/// Ask nix to lock `flake_ref`, for forms we cannot resolve ourselves.
+async fn flake_metadata_revision(flake_ref: &FlakeRef) -> Result<RevisionInfo> {
+    let url = flake_ref.to_url();
+    ensure_online(&url)?;
+    let output = Command::new("nix")
+        .args(["--extra-experimental-features", "nix-command flakes"])
+        .args(["flake", "metadata", "--json", &url])
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(Error::NixRegistry {
+            reason: format!(
+                "nix flake metadata {} failed: {}",
+                url,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        });
+    }
+    let metadata: FlakeMetadataJson = serde_json::from_slice(&output.stdout)?;
+    let rev = metadata.revision.ok_or_else(|| Error::NixRegistry {
+        reason: format!("{} does not resolve to a commit", url),
+    })?;
+    Ok(RevisionInfo {
+        nixpkgs_revision: rev,
+        nixos_release: flake_ref.git_ref().and_then(release_from_ref),
+        last_modified: metadata.last_modified,
+    })
+}
+
+/// The commit `git_ref` (or the default branch) of a GitHub repository points to.
+async fn github_commit(
+    fetcher: &dyn Fetcher,
+    host: Option<&str>,
+    owner: &str,
+    repo: &str,
+    git_ref: Option<&str>,
+) -> Result<String> {
+    let config = configfile::get_config().unwrap_or_default();
+    let default_api = config
+        .github_api_url
+        .as_deref()
+        .unwrap_or(DEFAULT_GITHUB_API_URL)
+        .trim_end_matches('/');
+    let (api, token) = match host {
+        Some(host) if host != "github.com" => (format!("https://{}/api/v3", host), None),
+        // Only the configured API gets the token, not arbitrary hosts
+        _ => (
+            default_api.to_string(),
+            config
+                .github_token
+                .clone()
+                .or_else(|| std::env::var("GITHUB_TOKEN").ok())
+                .filter(|t| !t.is_empty()),
+        ),
+    };
+    let mut request = FetchRequest::new(format!(
+        "{}/repos/{}/{}/commits/{}",
+        api,
+        owner,
+        repo,
+        git_ref.unwrap_or("HEAD")
+    ))
+    .header("accept", "application/vnd.github+json");
+    if let Some(token) = token {
+        request = request.header("authorization", format!("Bearer {}", token));
+    }
+    let key = format!("github:{}", request.url);
+
+    cached_lookup(&key, async {
+        let body = fetch::get(fetcher, request).await?;
+        Ok(serde_json::from_slice::<GhResponse>(&body)?.sha)
+    })
+    .await
+}
+
+/// The nixpkgs commit a flake reference currently resolves to.
+pub(crate) async fn resolve_flake_ref(
+    fetcher: &dyn Fetcher,
+    flake_ref: &FlakeRef,
+) -> Result<RevisionInfo> {
+    let release = flake_ref.git_ref().and_then(release_from_ref);
+    if let Some(rev) = flake_ref.rev() {
+        return Ok(RevisionInfo {
+            nixpkgs_revision: rev.to_string(),
+            nixos_release: release,
+            last_modified: None,
+        });
+    }
+
+    match flake_ref {
+        FlakeRef::Forge {
+            forge: Forge::GitHub,
+            owner,
+            repo,
+            host,
+            git_ref,
+            ..
+        } => {
+            ensure_online(&flake_ref.to_url())?;
+            Ok(RevisionInfo {
+                nixpkgs_revision: github_commit(
+                    fetcher,
+                    host.as_deref(),
+                    owner,
+                    repo,
+                    git_ref.as_deref(),
+                )
+                .await?,
+                nixos_release: release,
+                last_modified: None,
+            })
+        }
+        // Channel tarballs have the commit in a `git-revision` file next to them
+        FlakeRef::Tarball { url } if url.ends_with("/nixexprs.tar.xz") => {
+            ensure_online(url)?;
+            let rev_url = format!("{}/git-revision", url.trim_end_matches("/nixexprs.tar.xz"));
+            let rev = cached_lookup(&format!("tarball:{}", url), async {
+                let body = fetch::get(fetcher, FetchRequest::new(rev_url)).await?;
+                Ok(String::from_utf8(body)?.trim().to_string())
+            })
             .await?;
-        let output = String::from_utf8(output.stdout)?;
-        let lines = output.split('\n').collect::<Vec<_>>();
-        let line = lines
-            .iter()
-            .find(|x| x.contains("global flake:nixpkgs"))
-            .ok_or_else(|| Error::NixRegistry {
-                reason: "no nixpkgs flake found".into(),
-            })?;
-        let path = line
-            .split(' ')
-            .collect::<Vec<_>>()
-            .get(2)
-            .ok_or_else(|| Error::NixRegistry {
-                reason: "invalid registry entry".into(),
-            })?
-            .to_string();
-
-        match path {
-            x if x.starts_with("github:NixOS/nixpkgs/") => {
-                let parts = x.split('/').collect::<Vec<_>>();
-                let channel = parts.last().ok_or_else(|| Error::NixRegistry {
-                    reason: "invalid github path".into(),
-                })?;
-                let output = reqwest::Client::new()
-                    .get(format!(
-                        "https://api.github.com/repos/NixOS/nixpkgs/commits/{}",
-                        channel
-                    ))
-                    .header(reqwest::header::USER_AGENT, "libsnow")
-                    .send()
-                    .await?
-                    .json::<GhResponse>()
-                    .await?;
-                Ok(RevisionInfo {
-                    nixpkgs_revision: output.sha,
+            let channel = url
+                .trim_end_matches("/nixexprs.tar.xz")
+                .rsplit('/')
+                .next()
+                .unwrap_or_default();
+            Ok(RevisionInfo {
+                nixpkgs_revision: rev.trim().to_string(),
+                nixos_release: release_from_ref(channel),
+                last_modified: None,
+            })
+        }
+        FlakeRef::Path { path, .. } => {
+            // nixpkgs sources from channels and flakes carry their commit
+            match tokio::fs::read_to_string(Path::new(path).join(".git-revision")).await {
+                Ok(rev) if is_commit(rev.trim()) => Ok(RevisionInfo {
+                    nixpkgs_revision: rev.trim().to_string(),
                     nixos_release: None,
-                })
+                    last_modified: None,
+                }),
+                _ if *IS_NIXOS => nixos_version_revision().await,
+                _ => flake_metadata_revision(flake_ref).await,
             }
-            _ => Err(Error::NixRegistry {
-                reason: "invalid nixpkgs flake path".into(),
-            }),
         }
+        _ => flake_metadata_revision(flake_ref).await,
     }
 }
 
-pub(crate) async fn get_registry_revision() -> Result<RevisionInfo> {
+pub(crate) async fn get_registry_revision(fetcher: &dyn Fetcher) -> Result<RevisionInfo> {
     let output = Command::new("nix")
         .arg("registry")
         .arg("list")
@@ -126,104 +587,278 @@ pub(crate) async fn get_registry_revision() -> Result<RevisionInfo> {
         reason: "no nixpkgs flake found in registry".into(),
     })?;
 
-    if url.starts_with("github:NixOS/nixpkgs/") {
-        let parts: Vec<&str> = url.split('/').collect();
-        let channel = *parts.last().ok_or_else(|| Error::NixRegistry {
-            reason: "invalid github path".into(),
-        })?;
-
-        if let Some(rev_part) = channel.split('?').find(|p| p.starts_with("rev=")) {
-            let rev = rev_part.strip_prefix("rev=").unwrap();
-            return Ok(RevisionInfo {
-                nixpkgs_revision: rev.to_string(),
-                nixos_release: None,
-            });
-        }
-
-        let channel = channel.split('?').next().unwrap_or(channel);
-
-        let output = reqwest::Client::new()
-            .get(format!(
-                "https://api.github.com/repos/NixOS/nixpkgs/commits/{}",
-                channel
-            ))
-            .header(reqwest::header::USER_AGENT, "libsnow")
-            .send()
-            .await?
-            .json::<GhResponse>()
-            .await?;
-        Ok(RevisionInfo {
-            nixpkgs_revision: output.sha,
-            nixos_release: None,
-        })
-    } else if url.starts_with("path:") {
-        if *IS_NIXOS {
-            let output = Command::new("nixos-version").arg("--json").output().await?;
-            let output = String::from_utf8(output.stdout)?;
-            let version: NixosVersionJson = serde_json::from_str(&output)?;
-            let release = version
-                .nixos_version
-                .split('.')
-                .take(2)
-                .collect::<Vec<_>>()
-                .join(".");
-            Ok(RevisionInfo {
-                nixpkgs_revision: version.nixpkgs_revision,
-                nixos_release: Some(release),
-            })
-        } else {
-            Err(Error::NixRegistry {
-                reason: "registry nixpkgs points to a store path but not on NixOS".into(),
-            })
-        }
-    } else {
-        Err(Error::NixRegistry {
-            reason: format!("unsupported nixpkgs registry entry: {}", url),
-        })
-    }
+    resolve_flake_ref(fetcher, &FlakeRef::parse(url)?).await
 }
 
-async fn get_channel_revision(channel: &str) -> Result<String> {
-    let url = format!("https://channels.nixos.org/{}/git-revision", channel);
-    let resp = reqwest::get(&url).await?.error_for_status()?;
-    let rev = resp.text().await?;
-    Ok(rev.trim().to_string())
+async fn get_channel_revision(fetcher: &dyn Fetcher, channel: &str) -> Result<String> {
+    ensure_online(channel)?;
+    let config = configfile::get_config().unwrap_or_default();
+    let base = config
+        .channels_url
+        .as_deref()
+        .unwrap_or(DEFAULT_CHANNELS_URL)
+        .trim_end_matches('/');
+    let url = format!("{}/{}/git-revision", base, channel);
+    cached_lookup(&format!("channel:{}", url), async {
+        let body = fetch::get(fetcher, FetchRequest::new(url.clone())).await?;
+        Ok(String::from_utf8(body)?.trim().to_string())
+    })
+    .await
 }
 
-pub(crate) async fn get_latest_nixpkgs_revision() -> Result<RevisionInfo> {
+pub(crate) async fn get_latest_nixpkgs_revision(fetcher: &dyn Fetcher) -> Result<RevisionInfo> {
     if *IS_NIXOS {
-        let output = Command::new("nixos-version").arg("--json").output().await?;
-        let output = String::from_utf8(output.stdout)?;
-
-        let version: NixosVersionJson = serde_json::from_str(&output)?;
-
-        // 24.11.12345678.abcdefg -> 24.11
-        let release = version
-            .nixos_version
-            .split('.')
-            .take(2)
-            .collect::<Vec<_>>()
-            .join(".");
-
+        let release = nixos_version_revision()
+            .await?
+            .nixos_release
+            .unwrap_or_else(|| "unstable".to_string());
         let channel = format!("nixos-{}", release);
-        match get_channel_revision(&channel).await {
+        match get_channel_revision(fetcher, &channel).await {
             Ok(rev) => Ok(RevisionInfo {
                 nixpkgs_revision: rev,
                 nixos_release: Some(release),
+                last_modified: None,
             }),
             Err(_) => {
-                let rev = get_channel_revision("nixos-unstable").await?;
+                let rev = get_channel_revision(fetcher, "nixos-unstable").await?;
                 Ok(RevisionInfo {
                     nixpkgs_revision: rev,
                     nixos_release: Some(release),
+                    last_modified: None,
                 })
             }
         }
     } else {
-        let rev = get_channel_revision("nixpkgs-unstable").await?;
+        let rev = get_channel_revision(fetcher, "nixpkgs-unstable").await?;
         Ok(RevisionInfo {
             nixpkgs_revision: rev,
             nixos_release: Some("unstable".to_string()),
+            last_modified: None,
         })
     }
 }
+
+/// Directories that may hold the user's nix-channels, most specific first.
+fn channel_roots() -> Vec<PathBuf> {
+    let home = Path::new(&*HOME);
+    let mut roots = vec![
+        home.join(".nix-defexpr/channels"),
+        home.join(".nix-defexpr/channels_root"),
+    ];
+    if let Some(state) = dirs::state_dir() {
+        roots.push(state.join("nix/profiles/channels"));
+    }
+    if let Ok(user) = std::env::var("USER") {
+        roots.push(PathBuf::from(format!(
+            "/nix/var/nix/profiles/per-user/{}/channels",
+            user
+        )));
+    }
+    roots.push(PathBuf::from(
+        "/nix/var/nix/profiles/per-user/root/channels",
+    ));
+    roots
+}
+
+async fn read_trimmed(path: &Path) -> Option<String> {
+    let content = tokio::fs::read_to_string(path).await.ok()?;
+    let content = content.trim();
+    (!content.is_empty()).then(|| content.to_string())
+}
+
+/// The revision of a nixpkgs source tree unpacked from a channel, from its
+/// `.git-revision`, or else the short commit at the end of its
+/// `svn-revision` (e.g. `24.11.710315.abcdef12`).
+async fn channel_dir_revision(fetcher: &dyn Fetcher, dir: &Path) -> Result<Option<RevisionInfo>> {
+    let release = read_trimmed(&dir.join(".version")).await;
+    if let Some(rev) = read_trimmed(&dir.join(".git-revision")).await
+        && is_commit(&rev)
+    {
+        return Ok(Some(RevisionInfo {
+            nixpkgs_revision: rev,
+            nixos_release: release,
+            last_modified: None,
+        }));
+    }
+
+    let Some(svn) = read_trimmed(&dir.join("svn-revision")).await else {
+        return Ok(None);
+    };
+    let short = svn.rsplit('.').next().unwrap_or_default();
+    if short.is_empty() || !short.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::Config {
+            reason: format!("unrecognized svn-revision {} in {}", svn, dir.display()),
+        });
+    }
+    ensure_online(&format!("short revision {}", short))?;
+    let rev = github_commit(fetcher, None, "NixOS", "nixpkgs", Some(short)).await?;
+    Ok(Some(RevisionInfo {
+        nixpkgs_revision: rev,
+        nixos_release: release.or_else(|| {
+            let mut parts = svn.split('.');
+            Some(format!("{}.{}", parts.next()?, parts.next()?))
+        }),
+        last_modified: None,
+    }))
+}
+
+/// The revision of the nix-channel `nix-env -iA` installs from, see
+/// [`nixenv::get_channel`](crate::nixenv::get_channel).
+pub(crate) async fn get_nix_channel_revision(fetcher: &dyn Fetcher) -> Result<RevisionInfo> {
+    let channel = tokio::task::spawn_blocking(crate::nixenv::get_channel)
+        .await
+        .map_err(std::io::Error::other)??;
+    for root in channel_roots() {
+        let dir = root.join(&channel);
+        if let Some(info) = channel_dir_revision(fetcher, &dir).await? {
+            debug!("Channel {} is at {}", channel, info.nixpkgs_revision);
+            return Ok(info);
+        }
+    }
+    Err(Error::Config {
+        reason: format!("no revision found for channel {}", channel),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REV: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn registry_nixpkgs_entry() {
+        let output = "\
+user   flake:nixpkgs path:/home/user/nixpkgs
+system flake:nixpkgs path:/nix/store/abc-source?lastModified=1&narHash=sha256-x
+global flake:nix-darwin github:LnL7/nix-darwin
+global flake:nixpkgs github:NixOS/nixpkgs/nixpkgs-unstable
+";
+        assert_eq!(
+            registry_nixpkgs_url(output).unwrap(),
+            "github:NixOS/nixpkgs/nixpkgs-unstable"
+        );
+        assert!(registry_nixpkgs_url("global flake:home-manager github:a/b\n").is_err());
+    }
+
+    #[test]
+    fn parse_forges() {
+        assert_eq!(
+            FlakeRef::parse("github:NixOS/nixpkgs/nixos-24.11").unwrap(),
+            FlakeRef::Forge {
+                forge: Forge::GitHub,
+                owner: "NixOS".into(),
+                repo: "nixpkgs".into(),
+                host: None,
+                git_ref: Some("nixos-24.11".into()),
+                rev: None,
+            }
+        );
+        assert_eq!(
+            FlakeRef::parse(&format!("github:NixOS/nixpkgs/{}", REV)).unwrap(),
+            FlakeRef::Forge {
+                forge: Forge::GitHub,
+                owner: "NixOS".into(),
+                repo: "nixpkgs".into(),
+                host: None,
+                git_ref: None,
+                rev: Some(REV.into()),
+            }
+        );
+        assert_eq!(
+            FlakeRef::parse("gitlab:group/nixpkgs?host=gitlab.example.com&ref=main").unwrap(),
+            FlakeRef::Forge {
+                forge: Forge::GitLab,
+                owner: "group".into(),
+                repo: "nixpkgs".into(),
+                host: Some("gitlab.example.com".into()),
+                git_ref: Some("main".into()),
+                rev: None,
+            }
+        );
+        assert!(matches!(
+            FlakeRef::parse("sourcehut:~user/nixpkgs").unwrap(),
+            FlakeRef::Forge {
+                forge: Forge::SourceHut,
+                ..
+            }
+        ));
+        assert!(FlakeRef::parse("github:NixOS").is_err());
+    }
+
+    #[test]
+    fn parse_rev_query() {
+        let flake_ref = FlakeRef::parse(&format!(
+            "git+https://github.com/NixOS/nixpkgs?ref=refs%2Fheads%2Fnixos-24.11&rev={}",
+            REV
+        ))
+        .unwrap();
+        assert_eq!(
+            flake_ref,
+            FlakeRef::Git {
+                url: "https://github.com/NixOS/nixpkgs".into(),
+                git_ref: Some("refs/heads/nixos-24.11".into()),
+                rev: Some(REV.into()),
+            }
+        );
+        assert_eq!(flake_ref.rev(), Some(REV));
+    }
+
+    #[test]
+    fn parse_paths_tarballs_and_indirect() {
+        assert_eq!(
+            FlakeRef::parse(&format!("path:/nix/store/abc-source?rev={}", REV)).unwrap(),
+            FlakeRef::Path {
+                path: "/nix/store/abc-source".into(),
+                rev: Some(REV.into()),
+            }
+        );
+        assert_eq!(
+            FlakeRef::parse("/home/user/nixpkgs").unwrap(),
+            FlakeRef::Path {
+                path: "/home/user/nixpkgs".into(),
+                rev: None,
+            }
+        );
+        assert_eq!(
+            FlakeRef::parse("https://channels.nixos.org/nixos-24.11/nixexprs.tar.xz").unwrap(),
+            FlakeRef::Tarball {
+                url: "https://channels.nixos.org/nixos-24.11/nixexprs.tar.xz".into(),
+            }
+        );
+        assert_eq!(
+            FlakeRef::parse("tarball+https://example.com/nixpkgs.tar.gz").unwrap(),
+            FlakeRef::Tarball {
+                url: "https://example.com/nixpkgs.tar.gz".into(),
+            }
+        );
+        assert_eq!(
+            FlakeRef::parse("flake:nixpkgs/nixos-24.11").unwrap(),
+            FlakeRef::Indirect {
+                id: "nixpkgs".into(),
+                git_ref: Some("nixos-24.11".into()),
+                rev: None,
+            }
+        );
+        assert_eq!(
+            FlakeRef::parse("nixpkgs").unwrap(),
+            FlakeRef::Indirect {
+                id: "nixpkgs".into(),
+                git_ref: None,
+                rev: None,
+            }
+        );
+        assert!(FlakeRef::parse("ftp://example.com/nixpkgs").is_err());
+    }
+
+    #[test]
+    fn to_url_round_trips() {
+        for url in [
+            "github:NixOS/nixpkgs?ref=nixos-24.11",
+            "git+https://example.com/nixpkgs?ref=main",
+            "flake:nixpkgs",
+        ] {
+            assert_eq!(FlakeRef::parse(url).unwrap().to_url(), url);
+        }
+    }
+}
@@ -1,11 +1,14 @@
+use super::details::{parse_json_column, parse_licenses};
+use super::platform::{KNOWN_SYSTEMS, Platform, parse_platforms};
 use crate::Result;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
-use std::{fs, path::Path};
+use sha2::{Digest, Sha256};
+use std::{fs, path::Path, time::UNIX_EPOCH};
 use tantivy::{
     Document, Index, Searcher, TantivyDocument, Term,
-    query::{BooleanQuery, BoostQuery, Occur, Query, QueryParser, TermQuery},
-    schema::{IndexRecordOption, STORED, STRING, Schema, TextFieldIndexing, TextOptions},
+    query::{BooleanQuery, BoostQuery, ConstScoreQuery, Occur, Query, QueryParser, TermQuery},
+    schema::{INDEXED, IndexRecordOption, STORED, STRING, Schema, TextFieldIndexing, TextOptions},
 };
 use tracing::debug;
 
@@ -15,6 +18,12 @@ pub struct DbSearcher {
     fuzzy_parser: QueryParser,
     attr_exact: tantivy::schema::Field,
     attr_ngram: tantivy::schema::Field,
+    broken: tantivy::schema::Field,
+    insecure: tantivy::schema::Field,
+    unfree: tantivy::schema::Field,
+    license_family: tantivy::schema::Field,
+    platforms: tantivy::schema::Field,
+    bad_platforms: tantivy::schema::Field,
 }
 
 struct SearchFields {
@@ -29,6 +38,9 @@ struct SearchFields {
     attr_default: tantivy::schema::Field,
     pname_default: tantivy::schema::Field,
     desc_default: tantivy::schema::Field,
+    license_family: tantivy::schema::Field,
+    platforms: tantivy::schema::Field,
+    bad_platforms: tantivy::schema::Field,
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -128,6 +140,7 @@ pub struct SearchQuery<'a> {
     pub query: &'a str,
     pub limit: usize,
     pub score_threshold: f32,
+    pub filters: SearchFilters<'a>,
 }
 
 impl<'a> Default for SearchQuery<'a> {
@@ -136,10 +149,116 @@ impl<'a> Default for SearchQuery<'a> {
             query: "",
             limit: 10,
             score_threshold: 10.0,
+            filters: SearchFilters::default(),
+        }
+    }
+}
+
+/// Facets applied inside the search query, so limits and scores
+/// only account for packages that pass every filter.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchFilters<'a> {
+    pub exclude_broken: bool,
+    pub exclude_insecure: bool,
+    pub exclude_unfree: bool,
+    /// License family such as `gpl`, `mit` or `apache` (case-insensitive).
+    pub license_family: Option<&'a str>,
+    /// Only return packages available on this system, e.g. `NIXARCH`.
+    pub platform: Option<&'a str>,
+}
+
+/// Marker indexed for packages that do not restrict `meta.platforms`.
+const ALL_PLATFORMS: &str = "*";
+
+/// Version of the index layout. Bump whenever `build_schema` or the way
+/// documents are indexed changes, so existing indexes get rebuilt.
+const INDEX_SCHEMA_VERSION: u32 = 1;
+
+/// Written inside the index directory to record what the index was built from.
+const INDEX_MANIFEST: &str = "libsnow-index.json";
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct IndexManifest {
+    schema_version: u32,
+    db_revision: String,
+    db_sha256: String,
+    db_size: u64,
+    db_modified: u64,
+}
+
+impl IndexManifest {
+    fn for_db(db_path: &Path) -> Result<Self> {
+        let meta = fs::metadata(db_path)?;
+        Ok(Self {
+            schema_version: INDEX_SCHEMA_VERSION,
+            db_revision: db_revision(db_path),
+            db_sha256: db_sha256(db_path)?,
+            db_size: meta.len(),
+            db_modified: modified_nanos(&meta),
+        })
+    }
+
+    /// Why the index is out of date for `db_path`, if it is. The checksum is
+    /// only computed when the size or modification time differ; when it still
+    /// matches, the recorded size and modification time are updated.
+    fn stale_reason(&mut self, db_path: &Path) -> Result<Option<String>> {
+        if self.schema_version != INDEX_SCHEMA_VERSION {
+            return Ok(Some(format!(
+                "index schema version {} does not match {}",
+                self.schema_version, INDEX_SCHEMA_VERSION
+            )));
+        }
+        let revision = db_revision(db_path);
+        if self.db_revision != revision {
+            return Ok(Some(format!(
+                "index was built for revision {}, not {}",
+                self.db_revision, revision
+            )));
+        }
+        let meta = fs::metadata(db_path)?;
+        if self.db_size == meta.len() && self.db_modified == modified_nanos(&meta) {
+            return Ok(None);
+        }
+        if self.db_sha256 != db_sha256(db_path)? {
+            return Ok(Some(
+                "database checksum changed since the index was built".into(),
+            ));
         }
+        self.db_size = meta.len();
+        self.db_modified = modified_nanos(&meta);
+        Ok(None)
     }
 }
 
+fn write_manifest(path: &Path, manifest: &IndexManifest) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(manifest)?)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn db_revision(db_path: &Path) -> String {
+    db_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+fn db_sha256(db_path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut fs::File::open(db_path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn modified_nanos(meta: &fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
 fn build_schema() -> (Schema, SearchFields) {
     let ngram_indexing = TextFieldIndexing::default()
         .set_tokenizer("ngram3")
@@ -157,15 +276,19 @@ fn build_schema() -> (Schema, SearchFields) {
     let version = schema_builder.add_text_field("version", STORED);
     let pname = schema_builder.add_text_field("pname", STORED);
     let description = schema_builder.add_text_field("description", STORED);
-    let broken = schema_builder.add_i64_field("broken", STORED);
-    let insecure = schema_builder.add_i64_field("insecure", STORED);
-    let unfree = schema_builder.add_i64_field("unfree", STORED);
+    let broken = schema_builder.add_i64_field("broken", STORED | INDEXED);
+    let insecure = schema_builder.add_i64_field("insecure", STORED | INDEXED);
+    let unfree = schema_builder.add_i64_field("unfree", STORED | INDEXED);
 
     let attr_exact = schema_builder.add_text_field("attribute_exact", STRING);
     let attr_default = schema_builder.add_text_field("attribute_default", default_opts.clone());
     let pname_default = schema_builder.add_text_field("pname_default", default_opts.clone());
     let desc_default = schema_builder.add_text_field("description_default", default_opts);
 
+    let license_family = schema_builder.add_text_field("license_family", STRING);
+    let platforms = schema_builder.add_text_field("platforms", STRING);
+    let bad_platforms = schema_builder.add_text_field("bad_platforms", STRING);
+
     let schema = schema_builder.build();
 
     (
@@ -182,6 +305,9 @@ fn build_schema() -> (Schema, SearchFields) {
             attr_default,
             pname_default,
             desc_default,
+            license_family,
+            platforms,
+            bad_platforms,
         },
     )
 }
@@ -200,6 +326,10 @@ fn fields_from_schema(schema: &Schema) -> Result<SearchFields> {
     let pname_default = schema.get_field("pname_default")?;
     let desc_default = schema.get_field("description_default")?;
 
+    let license_family = schema.get_field("license_family")?;
+    let platforms = schema.get_field("platforms")?;
+    let bad_platforms = schema.get_field("bad_platforms")?;
+
     Ok(SearchFields {
         attr_ngram,
         version,
@@ -212,6 +342,9 @@ fn fields_from_schema(schema: &Schema) -> Result<SearchFields> {
         attr_default,
         pname_default,
         desc_default,
+        license_family,
+        platforms,
+        bad_platforms,
     })
 }
 
@@ -231,7 +364,8 @@ fn fill_index(
     let mut stmt = db.prepare(
         "SELECT pkgs.attribute, pkgs.version, pkgs.pname, \
          meta.description, \
-         meta.broken, meta.insecure, meta.unfree \
+         meta.broken, meta.insecure, meta.unfree, \
+         meta.license, meta.platforms, meta.bad_platforms \
          FROM pkgs JOIN meta ON pkgs.attribute = meta.attribute",
     )?;
     let meta_iter = stmt.query_map([], |row| {
@@ -243,11 +377,14 @@ fn fill_index(
             row.get::<_, Option<i64>>(4),
             row.get::<_, Option<i64>>(5),
             row.get::<_, Option<i64>>(6),
+            row.get::<_, Option<String>>(7),
+            row.get::<_, Option<String>>(8),
+            row.get::<_, Option<String>>(9),
         ))
     })?;
 
     for meta in meta_iter {
-        let (attr, ver, pnm, desc, brk, insec, unfr) = meta?;
+        let (attr, ver, pnm, desc, brk, insec, unfr, lic, plats, bad_plats) = meta?;
         let mut doc = TantivyDocument::default();
 
         doc.add_text(fields.attr_ngram, &attr);
@@ -282,12 +419,61 @@ fn fill_index(
             doc.add_text(fields.desc_default, d);
         }
 
+        if let Some(license) = parse_json_column(lic.as_ref().ok().and_then(|x| x.as_deref())) {
+            let mut families: Vec<String> = parse_licenses(&license)
+                .iter()
+                .filter_map(|l| l.family())
+                .collect();
+            families.sort();
+            families.dedup();
+            for family in families {
+                doc.add_text(fields.license_family, &family);
+            }
+        }
+        let platforms = parse_json_column(plats.as_ref().ok().and_then(|x| x.as_deref()))
+            .map(|p| parse_platforms(&p))
+            .unwrap_or_default();
+        if platforms.is_empty() {
+            doc.add_text(fields.platforms, ALL_PLATFORMS);
+        }
+        for system in index_systems(&platforms, Some(ALL_PLATFORMS)) {
+            doc.add_text(fields.platforms, &system);
+        }
+        if let Some(bad) = parse_json_column(bad_plats.as_ref().ok().and_then(|x| x.as_deref())) {
+            for system in index_systems(&parse_platforms(&bad), None) {
+                doc.add_text(fields.bad_platforms, &system);
+            }
+        }
+
         index_writer.add_document(doc)?;
     }
 
     Ok(())
 }
 
+/// Expand platform entries into indexable system strings. Patterns are
+/// matched against [`KNOWN_SYSTEMS`]; ones we cannot evaluate index `undecided`.
+fn index_systems(platforms: &[Platform], undecided: Option<&str>) -> Vec<String> {
+    let mut systems = Vec::new();
+    for platform in platforms {
+        match platform {
+            Platform::System(s) => systems.push(s.clone()),
+            Platform::Pattern(_) => {
+                for system in KNOWN_SYSTEMS {
+                    match platform.matches(system) {
+                        Some(true) => systems.push(system.to_string()),
+                        Some(false) => {}
+                        None => systems.extend(undecided.map(str::to_string)),
+                    }
+                }
+            }
+        }
+    }
+    systems.sort();
+    systems.dedup();
+    systems
+}
+
 fn build_searcher_from_index(index: &Index) -> Result<DbSearcher> {
     let schema = index.schema();
     let fields = fields_from_schema(&schema)?;
@@ -316,23 +502,45 @@ fn build_searcher_from_index(index: &Index) -> Result<DbSearcher> {
         fuzzy_parser,
         attr_exact: fields.attr_exact,
         attr_ngram: fields.attr_ngram,
+        broken: fields.broken,
+        insecure: fields.insecure,
+        unfree: fields.unfree,
+        license_family: fields.license_family,
+        platforms: fields.platforms,
+        bad_platforms: fields.bad_platforms,
     })
 }
 
 pub fn build_search_index_in_dir(db: &rusqlite::Connection, index_dir: &Path) -> Result<()> {
-    if index_dir.exists() {
-        fs::remove_dir_all(index_dir)?;
+    // Build next to the final location and move it into place, so a crash
+    // or a concurrent reader never sees a half-written index.
+    let tmp_dir = index_dir.with_extension(format!("index.tmp.{}", std::process::id()));
+    if tmp_dir.exists() {
+        fs::remove_dir_all(&tmp_dir)?;
     }
-    fs::create_dir_all(index_dir)?;
+    fs::create_dir_all(&tmp_dir)?;
 
     let (schema, fields) = build_schema();
-    let index = Index::create_in_dir(index_dir, schema)?;
+    let index = Index::create_in_dir(&tmp_dir, schema)?;
     register_tokenizers(&index)?;
 
     let mut index_writer = index.writer(100_000_000)?;
     fill_index(db, &fields, &mut index_writer)?;
     index_writer.commit()?;
+    index_writer.wait_merging_threads()?;
+
+    if let Some(db_path) = db.path().filter(|p| !p.is_empty()) {
+        let manifest = IndexManifest::for_db(Path::new(db_path))?;
+        fs::write(
+            tmp_dir.join(INDEX_MANIFEST),
+            serde_json::to_string(&manifest)?,
+        )?;
+    }
 
+    if index_dir.exists() {
+        fs::remove_dir_all(index_dir)?;
+    }
+    fs::rename(&tmp_dir, index_dir)?;
     Ok(())
 }
 
@@ -342,6 +550,36 @@ pub(crate) fn get_searcher_from_dir(index_dir: &Path) -> Result<DbSearcher> {
     build_searcher_from_index(&index)
 }
 
+/// Open the index at `index_dir` if it is up to date for `db_path`,
+/// otherwise return why it needs to be rebuilt.
+pub(crate) fn open_index(
+    db_path: &Path,
+    index_dir: &Path,
+) -> std::result::Result<DbSearcher, String> {
+    if !index_dir.exists() {
+        return Err("no search index".into());
+    }
+    let manifest_path = index_dir.join(INDEX_MANIFEST);
+    let mut manifest: IndexManifest = fs::read_to_string(&manifest_path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .ok_or("missing or unreadable index manifest")?;
+    let recorded = (manifest.db_size, manifest.db_modified);
+    match manifest.stale_reason(db_path) {
+        Ok(None) if recorded != (manifest.db_size, manifest.db_modified) => {
+            // Skip the checksum next time the database is opened. Written
+            // aside and renamed, so concurrent readers never see half of it.
+            if let Err(e) = write_manifest(&manifest_path, &manifest) {
+                debug!("Failed to refresh index manifest: {}", e);
+            }
+        }
+        Ok(None) => {}
+        Ok(Some(reason)) => return Err(reason),
+        Err(e) => return Err(format!("failed to check database: {}", e)),
+    }
+    get_searcher_from_dir(index_dir).map_err(|e| format!("failed to open index: {}", e))
+}
+
 pub fn index_dir_for_db_path(db_path: &Path) -> std::path::PathBuf {
     db_path.with_extension("index")
 }
@@ -363,6 +601,114 @@ fn build_ngram_query(field: tantivy::schema::Field, query: &str) -> Box<dyn Quer
     Box::new(BooleanQuery::new(terms))
 }
 
+/// Attributes sharing trigrams with `attribute`, closest by edit distance
+/// first. Matches too far off to be a plausible typo are dropped.
+pub(crate) fn similar_attributes(
+    attribute: &str,
+    limit: usize,
+    dbsearcher: &DbSearcher,
+) -> Result<Vec<String>> {
+    let query = attribute.trim().to_lowercase();
+    let max_distance = (query.chars().count() / 3).max(2);
+    let top_docs = dbsearcher.searcher.search(
+        &build_ngram_query(dbsearcher.attr_ngram, &query),
+        &tantivy::collector::TopDocs::with_limit(limit.max(1) * 10),
+    )?;
+
+    let mut matches = Vec::new();
+    for (_, doc_address) in top_docs {
+        let doc: TantivyDocument = dbsearcher.searcher.doc(doc_address)?;
+        let result: SearchResult = serde_json::from_str(&doc.to_json(&dbsearcher.schema))?;
+        let distance = edit_distance(&query, &result.attribute.to_lowercase());
+        if distance <= max_distance {
+            matches.push((distance, result.attribute));
+        }
+    }
+    matches.sort_by(|(da, a), (db, b)| da.cmp(db).then(a.len().cmp(&b.len())).then(a.cmp(b)));
+    matches.dedup_by(|(_, a), (_, b)| a == b);
+    Ok(matches
+        .into_iter()
+        .take(limit)
+        .map(|(_, attr)| attr)
+        .collect())
+}
+
+/// Levenshtein distance between two strings, by `char`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != *cb);
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// Wrap `query` so that it only matches documents passing `filters`.
+/// Filter clauses are scored as zero so relevance is left untouched.
+fn apply_filters(
+    query: Box<dyn Query>,
+    filters: &SearchFilters,
+    dbsearcher: &DbSearcher,
+) -> Box<dyn Query> {
+    let unscored = |q: Box<dyn Query>| -> Box<dyn Query> { Box::new(ConstScoreQuery::new(q, 0.0)) };
+    let term =
+        |term: Term| -> Box<dyn Query> { Box::new(TermQuery::new(term, IndexRecordOption::Basic)) };
+
+    let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Must, query)];
+
+    for (enabled, field) in [
+        (filters.exclude_broken, dbsearcher.broken),
+        (filters.exclude_insecure, dbsearcher.insecure),
+        (filters.exclude_unfree, dbsearcher.unfree),
+    ] {
+        if enabled {
+            clauses.push((Occur::MustNot, term(Term::from_field_i64(field, 1))));
+        }
+    }
+
+    if let Some(family) = filters.license_family {
+        let family = family.trim().to_lowercase();
+        clauses.push((
+            Occur::Must,
+            unscored(term(Term::from_field_text(
+                dbsearcher.license_family,
+                &family,
+            ))),
+        ));
+    }
+
+    if let Some(platform) = filters.platform {
+        // Trimmed like `Platform::matches`, so the filter agrees with
+        // `Metadata::is_available_on`
+        let platform = platform.trim();
+        let supported = BooleanQuery::new(vec![
+            (
+                Occur::Should,
+                term(Term::from_field_text(dbsearcher.platforms, platform)),
+            ),
+            (
+                Occur::Should,
+                term(Term::from_field_text(dbsearcher.platforms, ALL_PLATFORMS)),
+            ),
+        ]);
+        clauses.push((Occur::Must, unscored(Box::new(supported))));
+        clauses.push((
+            Occur::MustNot,
+            term(Term::from_field_text(dbsearcher.bad_platforms, platform)),
+        ));
+    }
+
+    if clauses.len() == 1 {
+        return clauses.remove(0).1;
+    }
+    Box::new(BooleanQuery::new(clauses))
+}
+
 pub(crate) fn search(sq: &SearchQuery, dbsearcher: &DbSearcher) -> Result<Vec<SearchResult>> {
     let DbSearcher {
         searcher,
@@ -370,6 +716,7 @@ pub(crate) fn search(sq: &SearchQuery, dbsearcher: &DbSearcher) -> Result<Vec<Se
         fuzzy_parser,
         attr_exact,
         attr_ngram,
+        ..
     } = dbsearcher;
 
     let query_str = sq.query.trim();
@@ -388,11 +735,15 @@ pub(crate) fn search(sq: &SearchQuery, dbsearcher: &DbSearcher) -> Result<Vec<Se
     let (fuzzy_query, _) = fuzzy_parser.parse_query_lenient(&query_lower);
     let ngram_query = build_ngram_query(*attr_ngram, &query_lower);
 
-    let combined = BooleanQuery::new(vec![
-        (Occur::Should, exact_query),
-        (Occur::Should, fuzzy_query),
-        (Occur::Should, ngram_query),
-    ]);
+    let combined = apply_filters(
+        Box::new(BooleanQuery::new(vec![
+            (Occur::Should, exact_query),
+            (Occur::Should, fuzzy_query),
+            (Occur::Should, ngram_query),
+        ])),
+        &sq.filters,
+        dbsearcher,
+    );
 
     // Fetch extra candidates for post-hoc re-ranking by attribute length.
     let fetch_limit = sq.limit * 4;
@@ -0,0 +1,156 @@
+use super::{MAX_SUGGESTIONS, Metadata, PkgInfo, SearchQuery, SearchResult};
+use crate::{Error, Result};
+
+/// A value from one of the sources of a [`MetadataSet`].
+#[derive(Debug, Clone)]
+pub struct Sourced<T> {
+    /// Label of the source, as given to [`MetadataSet::add`].
+    pub source: String,
+    pub value: T,
+}
+
+/// Several labelled [`Metadata`] instances queried together, e.g. the
+/// system's NixOS release and nixpkgs unstable.
+///
+/// Sources are kept in order of preference: earlier sources win ties when
+/// merging search results and come first in [`MetadataSet::get_all`].
+#[derive(Default)]
+pub struct MetadataSet {
+    sources: Vec<(String, Metadata)>,
+}
+
+impl MetadataSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The current system revision labelled `current`, see [`Metadata::connect`],
+    /// followed by the latest nixpkgs labelled `latest`, see
+    /// [`Metadata::connect_latest`]. The latter is left out if both are the
+    /// same revision.
+    pub async fn connect() -> Result<Self> {
+        let current = Metadata::connect().await?;
+        let latest = Metadata::connect_latest().await?;
+        let mut set = Self::new();
+        let same = latest.nixpkgs_revision().is_some()
+            && latest.nixpkgs_revision() == current.nixpkgs_revision();
+        set.add("current", current);
+        if !same {
+            set.add("latest", latest);
+        }
+        Ok(set)
+    }
+
+    /// Add a source with the lowest preference, replacing any source with
+    /// the same label.
+    pub fn add(&mut self, label: impl Into<String>, md: Metadata) -> &mut Self {
+        let label = label.into();
+        self.sources.retain(|(l, _)| *l != label);
+        self.sources.push((label, md));
+        self
+    }
+
+    /// Remove a source, returning its [`Metadata`].
+    pub fn remove(&mut self, label: &str) -> Option<Metadata> {
+        let pos = self.sources.iter().position(|(l, _)| l == label)?;
+        Some(self.sources.remove(pos).1)
+    }
+
+    /// Make `label` the most preferred source. Returns `false` if there is no
+    /// such source.
+    pub fn prefer(&mut self, label: &str) -> bool {
+        match self.sources.iter().position(|(l, _)| l == label) {
+            Some(pos) => {
+                self.sources[..=pos].rotate_right(1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Source labels, most preferred first.
+    pub fn labels(&self) -> impl Iterator<Item = &str> {
+        self.sources.iter().map(|(l, _)| l.as_str())
+    }
+
+    /// The [`Metadata`] of a single source.
+    pub fn source(&self, label: &str) -> Option<&Metadata> {
+        self.sources
+            .iter()
+            .find(|(l, _)| l == label)
+            .map(|(_, md)| md)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Search every source, see [`Metadata::search`].
+    pub fn search(
+        &self,
+        query: &str,
+        limit: usize,
+        score_threshold: f32,
+    ) -> Result<Vec<Sourced<SearchResult>>> {
+        self.search_with(&SearchQuery {
+            query,
+            limit,
+            score_threshold,
+            ..Default::default()
+        })
+    }
+
+    /// Search every source with filters, see [`Metadata::search_with`].
+    /// Results are merged by score, with ties going to the preferred source,
+    /// and cut to `query.limit` in total.
+    pub fn search_with(&self, query: &SearchQuery) -> Result<Vec<Sourced<SearchResult>>> {
+        let mut results = Vec::new();
+        for (label, md) in &self.sources {
+            results.extend(md.search_with(query)?.into_iter().map(|r| Sourced {
+                source: label.clone(),
+                value: r,
+            }));
+        }
+        // Stable, so equal scores keep source order
+        results.sort_by(|a, b| b.value.score.total_cmp(&a.value.score));
+        results.truncate(query.limit);
+        Ok(results)
+    }
+
+    /// Look up `attribute` in every source that has it, most preferred first.
+    /// Fails with [`Error::PackageNotFound`] if no source has it, suggesting
+    /// similar attributes from the most preferred source.
+    pub fn get_all(&self, attribute: &str) -> Result<Vec<Sourced<PkgInfo>>> {
+        let mut found = Vec::new();
+        for (label, md) in &self.sources {
+            match md.get(attribute) {
+                Ok(info) => found.push(Sourced {
+                    source: label.clone(),
+                    value: info,
+                }),
+                Err(Error::PackageNotFound { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if found.is_empty() {
+            return Err(Error::PackageNotFound {
+                attr: attribute.to_string(),
+                suggestions: self
+                    .sources
+                    .first()
+                    .map(|(_, md)| md.suggest(attribute, MAX_SUGGESTIONS))
+                    .unwrap_or_default(),
+            });
+        }
+        Ok(found)
+    }
+
+    /// Look up `attribute` in the most preferred source that has it.
+    pub fn get(&self, attribute: &str) -> Result<Sourced<PkgInfo>> {
+        Ok(self.get_all(attribute)?.swap_remove(0))
+    }
+}
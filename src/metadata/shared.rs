@@ -0,0 +1,142 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use super::{Metadata, PkgInfo, SearchResult, lock::FileLock, search::DbSearcher};
+use crate::Result;
+
+/// Idle connections kept around for reuse.
+const MAX_IDLE_CONNECTIONS: usize = 4;
+
+/// A [`Metadata`] handle that can be shared between threads and tasks.
+///
+/// Clones are cheap and share one search index reader. SQLite connections
+/// are pooled: each call borrows one, opening a new connection if all are
+/// in use.
+#[derive(Clone)]
+pub struct SharedMetadata {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    idle: Mutex<Vec<Metadata>>,
+    searcher: Arc<DbSearcher>,
+    db_path: PathBuf,
+    nixpkgs_revision: Option<String>,
+    nixos_release: Option<String>,
+    nixpkgs_last_modified: Option<u64>,
+    lock: Option<Arc<FileLock>>,
+}
+
+impl From<Metadata> for SharedMetadata {
+    fn from(md: Metadata) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                searcher: md.searcher.clone(),
+                db_path: md.db_path.clone(),
+                nixpkgs_revision: md.nixpkgs_revision.clone(),
+                nixos_release: md.nixos_release.clone(),
+                nixpkgs_last_modified: md.nixpkgs_last_modified,
+                lock: md._lock.clone(),
+                idle: Mutex::new(vec![md]),
+            }),
+        }
+    }
+}
+
+impl SharedMetadata {
+    /// Open from a `.db` file, see [`Metadata::open`].
+    pub fn open(db_path: &Path) -> Result<Self> {
+        Ok(Metadata::open(db_path)?.into())
+    }
+
+    /// Run `f` with a pooled [`Metadata`]. This blocks the current thread,
+    /// so from async code prefer [`SharedMetadata::run`].
+    pub fn with<R>(&self, f: impl FnOnce(&Metadata) -> R) -> Result<R> {
+        let md = self.checkout()?;
+        let result = f(&md);
+        self.checkin(md);
+        Ok(result)
+    }
+
+    /// Run `f` with a pooled [`Metadata`] on tokio's blocking thread pool.
+    pub async fn run<R, F>(&self, f: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&Metadata) -> Result<R> + Send + 'static,
+    {
+        let this = self.clone();
+        tokio::task::spawn_blocking(move || this.with(f)?)
+            .await
+            .map_err(std::io::Error::other)?
+    }
+
+    /// Async [`Metadata::search`].
+    pub async fn search(
+        &self,
+        query: &str,
+        limit: usize,
+        score_threshold: f32,
+    ) -> Result<Vec<SearchResult>> {
+        let query = query.to_string();
+        self.run(move |md| md.search(&query, limit, score_threshold))
+            .await
+    }
+
+    /// Async [`Metadata::get`].
+    pub async fn get(&self, attribute: &str) -> Result<PkgInfo> {
+        let attribute = attribute.to_string();
+        self.run(move |md| md.get(&attribute)).await
+    }
+
+    /// Async [`Metadata::get_by_pname`].
+    pub async fn get_by_pname(&self, pname: &str) -> Result<Vec<PkgInfo>> {
+        let pname = pname.to_string();
+        self.run(move |md| md.get_by_pname(&pname)).await
+    }
+
+    pub fn nixpkgs_revision(&self) -> Option<&str> {
+        self.inner.nixpkgs_revision.as_deref()
+    }
+
+    pub fn nixos_release(&self) -> Option<&str> {
+        self.inner.nixos_release.as_deref()
+    }
+
+    pub fn nixpkgs_last_modified(&self) -> Option<u64> {
+        self.inner.nixpkgs_last_modified
+    }
+
+    pub fn db_path(&self) -> &Path {
+        &self.inner.db_path
+    }
+
+    fn checkout(&self) -> Result<Metadata> {
+        if let Some(md) = self.idle().pop() {
+            return Ok(md);
+        }
+        let inner = &self.inner;
+        Ok(Metadata {
+            conn: rusqlite::Connection::open(&inner.db_path)?,
+            searcher: inner.searcher.clone(),
+            db_path: inner.db_path.clone(),
+            nixpkgs_revision: inner.nixpkgs_revision.clone(),
+            nixos_release: inner.nixos_release.clone(),
+            nixpkgs_last_modified: inner.nixpkgs_last_modified,
+            _lock: inner.lock.clone(),
+        })
+    }
+
+    fn checkin(&self, md: Metadata) {
+        let mut idle = self.idle();
+        if idle.len() < MAX_IDLE_CONNECTIONS {
+            idle.push(md);
+        }
+    }
+
+    fn idle(&self) -> std::sync::MutexGuard<'_, Vec<Metadata>> {
+        // A panic while holding the lock cannot leave the Vec inconsistent
+        self.inner.idle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,122 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::{Error, Result};
+
+/// Extension of the SHA-256 manifest published next to `<rev>.db`.
+pub const MANIFEST_EXTENSION: &str = "sha256";
+/// Extension of the detached signature of the manifest.
+pub const SIGNATURE_EXTENSION: &str = "sig";
+
+/// Build a `sha256sum`-style manifest line for a database file.
+pub fn checksum_manifest(bytes: &[u8], file_name: &str) -> String {
+    format!("{:x}  {}\n", Sha256::digest(bytes), file_name)
+}
+
+/// Sign a manifest with a Nix-style secret key (`name:base64`, as produced by
+/// `nix-store --generate-binary-cache-key`). Returns `name:base64(signature)`.
+pub fn sign_manifest(manifest: &str, secret_key: &str) -> Result<String> {
+    let (name, key) = split_key(secret_key)?;
+    let signing_key = match key.len() {
+        64 => {
+            let bytes: [u8; 64] = key.as_slice().try_into().expect("length checked");
+            SigningKey::from_keypair_bytes(&bytes).map_err(|e| Error::Config {
+                reason: format!("invalid signing key {}: {}", name, e),
+            })?
+        }
+        32 => {
+            let bytes: [u8; 32] = key.as_slice().try_into().expect("length checked");
+            SigningKey::from_bytes(&bytes)
+        }
+        n => {
+            return Err(Error::Config {
+                reason: format!("invalid signing key {}: unexpected length {}", name, n),
+            });
+        }
+    };
+    let signature = signing_key.sign(manifest.as_bytes());
+    Ok(format!(
+        "{}:{}\n",
+        name,
+        STANDARD.encode(signature.to_bytes())
+    ))
+}
+
+/// Check that `manifest` is signed by one of `trusted_keys`, that it names
+/// `<rev>.db` and that it matches the SHA-256 of `bytes`.
+pub(crate) fn verify_database(
+    bytes: &[u8],
+    rev: &str,
+    manifest: &str,
+    signature: &str,
+    trusted_keys: &[String],
+) -> Result<()> {
+    let (name, sig) = split_key(signature.trim()).map_err(|_| Error::InvalidDatabase {
+        reason: "malformed database signature".into(),
+    })?;
+    let sig: [u8; 64] = sig
+        .as_slice()
+        .try_into()
+        .map_err(|_| Error::InvalidDatabase {
+            reason: "malformed database signature".into(),
+        })?;
+    let sig = Signature::from_bytes(&sig);
+
+    let verified = trusted_keys
+        .iter()
+        .filter_map(|k| split_key(k).ok())
+        .filter(|(key_name, _)| *key_name == name)
+        .filter_map(|(_, key)| {
+            let key: [u8; 32] = key.as_slice().try_into().ok()?;
+            VerifyingKey::from_bytes(&key).ok()
+        })
+        .any(|key| key.verify(manifest.as_bytes(), &sig).is_ok());
+    if !verified {
+        return Err(Error::InvalidDatabase {
+            reason: format!("signature by {} does not match any trusted key", name),
+        });
+    }
+
+    let expected = manifest_checksum(manifest, &format!("{}.db", rev))?;
+    let actual = format!("{:x}", Sha256::digest(bytes));
+    if !expected.eq_ignore_ascii_case(&actual) {
+        return Err(Error::InvalidDatabase {
+            reason: format!("checksum mismatch: expected {}, got {}", expected, actual),
+        });
+    }
+    Ok(())
+}
+
+/// The checksum in a manifest that must consist of exactly one
+/// `<sha256>  <file_name>` line.
+fn manifest_checksum<'a>(manifest: &'a str, file_name: &str) -> Result<&'a str> {
+    let line = manifest.strip_suffix('\n').unwrap_or(manifest);
+    let (checksum, name) = line
+        .split_once("  ")
+        .filter(|_| !line.contains('\n'))
+        .ok_or_else(|| Error::InvalidDatabase {
+            reason: "malformed database manifest".into(),
+        })?;
+    if checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::InvalidDatabase {
+            reason: "malformed checksum in database manifest".into(),
+        });
+    }
+    if name != file_name {
+        return Err(Error::InvalidDatabase {
+            reason: format!("manifest is for {}, expected {}", name, file_name),
+        });
+    }
+    Ok(checksum)
+}
+
+fn split_key(key: &str) -> Result<(&str, Vec<u8>)> {
+    let (name, data) = key.trim().split_once(':').ok_or_else(|| Error::Config {
+        reason: "key must be of the form name:base64".into(),
+    })?;
+    let data = STANDARD.decode(data).map_err(|e| Error::Config {
+        reason: format!("invalid base64 in key {}: {}", name, e),
+    })?;
+    Ok((name, data))
+}
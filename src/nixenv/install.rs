@@ -1,13 +1,23 @@
 use super::{get_channel, list::list};
 use crate::{Error, PackageAttr, Result, metadata::Metadata};
 use tokio::process::Command;
+use tracing::warn;
 
 pub async fn install(pkgs: &[&str], md: &Metadata) -> Result<()> {
     let installed = list(md).await?;
     let mut pkgs_to_install = Vec::new();
     for pkg in pkgs {
+        // Attrs missing from the database are left to nix-env
+        let pkg = match md.resolve_installable(pkg) {
+            Ok(attr) => attr,
+            Err(e @ Error::PackageNotFound { .. }) => {
+                warn!("{}", e);
+                pkg.to_string()
+            }
+            Err(e) => return Err(e),
+        };
         if installed.iter().any(|x| match x.attr {
-            PackageAttr::NixPkgs { ref attr } => attr == pkg,
+            PackageAttr::NixPkgs { ref attr } => *attr == pkg,
             _ => false,
         }) {
             println!("Package {} is already installed", pkg);
@@ -8,6 +8,8 @@ struct EnvPackage {
     pname: String,
 }
 
+/// Packages installed with `nix-env`. Attributes are looked up in `md`, which
+/// should come from [`Metadata::connect_channel`] to match the channel in use.
 pub async fn list(md: &Metadata) -> Result<Vec<Package>> {
     let output = std::process::Command::new("nix-env")
         .arg("-q")
@@ -2,7 +2,7 @@ use super::get_channel;
 use crate::{Error, PackageUpdate, Result, metadata::Metadata, nixenv::list::list, utils};
 
 pub async fn updatable(md: &Metadata) -> Result<Vec<PackageUpdate>> {
-    utils::misc::updatable(list(md).await?).await
+    utils::misc::updatable_channel(list(md).await?).await
 }
 
 pub async fn update(pkgs: &[&str], md: &Metadata) -> Result<()> {
@@ -2,7 +2,10 @@ use crate::{
     Error, Result,
     config::configfile::{self, ConfigMode},
     metadata::Metadata,
-    nixos::list::list_systempackages,
+    nixos::{
+        enable_prefix, ensure_target_available, is_known_target, list::list_systempackages,
+        removal_prefix, resolve_target,
+    },
     toml as tomlcfg,
 };
 use toml::Value as TomlValue;
@@ -18,18 +21,18 @@ pub fn prepare(installs: &[&str], removes: &[&str], md: &Metadata) -> Result<Str
 
     let mut pkgs_to_install = vec![];
     for pkg in installs {
-        if md.get(pkg).is_ok() {
-            if installed.contains(&pkg.to_string()) {
-                debug!("{} is already installed", pkg);
-            } else {
-                pkgs_to_install.push(pkg.to_string());
-            }
+        let attr = resolve_target(md, pkg)?;
+        if installed.contains(&attr) {
+            debug!("{} is already installed", attr);
+        } else if !pkgs_to_install.contains(&attr) {
+            ensure_target_available(md, &attr)?;
+            pkgs_to_install.push(attr);
         }
     }
 
     let mut pkgs_to_remove = vec![];
     for pkg in removes {
-        if md.get(pkg).is_ok() {
+        if is_known_target(md, pkg) {
             if installed.contains(&pkg.to_string()) {
                 pkgs_to_remove.push(pkg.to_string());
             } else {
@@ -50,8 +53,8 @@ pub fn prepare(installs: &[&str], removes: &[&str], md: &Metadata) -> Result<Str
             let mut pf = tomlcfg::read_system(std::path::Path::new(&path))?;
 
             for attr in &pkgs_to_install {
-                if md.has_program_option(attr) {
-                    let key = format!("programs.{}.enable", attr);
+                if let Some(prefix) = enable_prefix(md, attr) {
+                    let key = format!("{}.enable", prefix);
                     if pf.options.get(&key) != Some(&TomlValue::Boolean(true)) {
                         pf.options.insert(key, TomlValue::Boolean(true));
                     }
@@ -62,7 +65,7 @@ pub fn prepare(installs: &[&str], removes: &[&str], md: &Metadata) -> Result<Str
 
             for attr in &pkgs_to_remove {
                 pf.packages.retain(|p| p != attr);
-                let prefix = format!("programs.{}.", attr);
+                let prefix = format!("{}.", removal_prefix(md, attr));
                 let keys_to_remove: Vec<String> = pf
                     .options
                     .keys()
@@ -82,8 +85,8 @@ pub fn prepare(installs: &[&str], removes: &[&str], md: &Metadata) -> Result<Str
 
             let mut install_arr_pkgs = vec![];
             for attr in &pkgs_to_install {
-                if md.has_program_option(attr) {
-                    let key = format!("programs.{}.enable", attr);
+                if let Some(prefix) = enable_prefix(md, attr) {
+                    let key = format!("{}.enable", prefix);
                     current = nix_editor::write::write(&current, &key, "true").map_err(|e| {
                         Error::NixEditor {
                             reason: e.to_string(),
@@ -115,7 +118,7 @@ pub fn prepare(installs: &[&str], removes: &[&str], md: &Metadata) -> Result<Str
 
             let mut remove_arr_pkgs = vec![];
             for attr in &pkgs_to_remove {
-                let key = format!("programs.{}.enable", attr);
+                let key = format!("{}.enable", removal_prefix(md, attr));
                 if nix_editor::read::readvalue(&current, &key).is_ok() {
                     current =
                         nix_editor::write::deref(&current, &key).map_err(|e| Error::NixEditor {
@@ -4,7 +4,7 @@ use crate::{
     config::configfile::{self, ConfigMode},
     dbus,
     metadata::Metadata,
-    nixos::list::list_systempackages,
+    nixos::{enable_prefix, ensure_target_available, list::list_systempackages, resolve_target},
     toml as tomlcfg,
 };
 use tokio::io::AsyncWriteExt;
@@ -47,12 +47,12 @@ fn prepare_install(
 
     let mut pkgs_to_install = vec![];
     for pkg in pkgs {
-        if md.get(pkg).is_ok() {
-            if installed.contains(&pkg.to_string()) {
-                debug!("{} is already installed", pkg);
-            } else {
-                pkgs_to_install.push(pkg.to_string());
-            }
+        let attr = resolve_target(md, pkg)?;
+        if installed.contains(&attr) {
+            debug!("{} is already installed", attr);
+        } else if !pkgs_to_install.contains(&attr) {
+            ensure_target_available(md, &attr)?;
+            pkgs_to_install.push(attr);
         }
     }
 
@@ -67,8 +67,8 @@ fn prepare_install(
             let path = tomlcfg::system_config_file_path()?;
             let mut pf = tomlcfg::read_system(std::path::Path::new(&path))?;
             for attr in &pkgs_to_install {
-                if md.has_program_option(attr) {
-                    let key = format!("programs.{}.enable", attr);
+                if let Some(prefix) = enable_prefix(md, attr) {
+                    let key = format!("{}.enable", prefix);
                     if pf.options.get(&key) != Some(&TomlValue::Boolean(true)) {
                         pf.options.insert(key, TomlValue::Boolean(true));
                     }
@@ -83,8 +83,8 @@ fn prepare_install(
             let mut current = config.read_system_config_file()?;
             let mut arr_pkgs = vec![];
             for attr in &pkgs_to_install {
-                if md.has_program_option(attr) {
-                    let key = format!("programs.{}.enable", attr);
+                if let Some(prefix) = enable_prefix(md, attr) {
+                    let key = format!("{}.enable", prefix);
                     current = nix_editor::write::write(&current, &key, "true").map_err(|e| {
                         Error::NixEditor {
                             reason: e.to_string(),
@@ -138,15 +138,26 @@ pub async fn install_spawn(
     let config = configfile::get_config()?;
     let (content, output_path, _arguments) = prepare_install(pkgs, md, &config)?;
 
+    spawn_config_helper(&content, &output_path, &config, auth_method).await
+}
+
+/// Spawn the helper that writes `content`, piped to its stdin, to
+/// `output_path` and rebuilds. Used by install and migrate.
+pub(crate) async fn spawn_config_helper(
+    content: &str,
+    output_path: &str,
+    config: &configfile::LibSnowConfig,
+    auth_method: AuthMethod<'_>,
+) -> Result<tokio::process::Child> {
     let mut child = tokio::process::Command::new(match auth_method {
-        AuthMethod::Dbus => unreachable!("D-Bus path handled in install()"),
+        AuthMethod::Dbus => unreachable!("D-Bus path handled by the caller"),
         AuthMethod::Sudo => "sudo",
         AuthMethod::Custom(cmd) => cmd,
     })
     .arg(HELPER_EXEC)
     .arg("config")
     .arg("--output")
-    .arg(&output_path)
+    .arg(output_path)
     .args(if let Some(generations) = config.get_generation_count() {
         vec!["--generations".to_string(), generations.to_string()]
     } else {
@@ -154,13 +165,13 @@ pub async fn install_spawn(
     })
     .arg("--")
     .arg("switch")
-    .args(if let Some(flake) = config.flake {
+    .args(if let Some(flake) = &config.flake {
         vec![
             "--flake".to_string(),
-            if let Some(host) = config.host {
+            if let Some(host) = &config.host {
                 format!("{}#{}", flake, host)
             } else {
-                flake
+                flake.clone()
             },
         ]
     } else {
@@ -3,26 +3,34 @@ use crate::{
     config::configfile::{self, ConfigMode},
     metadata::Metadata,
     toml as tomlcfg,
+    utils::misc::enabled_options,
 };
 
 pub fn list_systempackages(md: &Metadata) -> Result<Vec<Package>> {
     let config = configfile::get_config()?;
 
-    let attrs: Vec<String> = match config.mode {
+    let (attrs, modules): (Vec<String>, Vec<String>) = match config.mode {
         ConfigMode::Toml => {
             let path = tomlcfg::system_config_file_path()?;
             let pf = tomlcfg::read_system(std::path::Path::new(&path))?;
             let mut attrs = pf.packages;
-            for key in pf.options.keys() {
+            let mut modules = Vec::new();
+            for (key, value) in &pf.options {
                 if let Some(rest) = key.strip_prefix("programs.")
                     && let Some(name) = rest.strip_suffix(".enable")
                     && !name.contains('.')
                     && !attrs.contains(&name.to_string())
                 {
                     attrs.push(name.to_string());
+                } else if let Some(path) = key.strip_suffix(".enable")
+                    && value.as_bool() == Some(true)
+                    && md.module(path).is_some()
+                    && !modules.contains(&path.to_string())
+                {
+                    modules.push(path.to_string());
                 }
             }
-            attrs
+            (attrs, modules)
         }
         ConfigMode::Nix => {
             let content = config.read_system_config_file()?;
@@ -36,42 +44,63 @@ pub fn list_systempackages(md: &Metadata) -> Result<Vec<Package>> {
                 .iter()
                 .map(|x| x.strip_prefix("pkgs.").unwrap_or(x).to_string())
                 .collect();
-            for name in md.all_program_option_attrs() {
-                let key = format!("programs.{}.enable", name);
-                if let Ok(val) = nix_editor::read::readvalue(&content, &key)
-                    && val.trim() == "true"
-                    && !attrs.contains(&name)
+            let mut modules = Vec::new();
+            for path in enabled_options(&content) {
+                if let Some(name) = path.strip_prefix("programs.")
+                    && md.has_program_option(name)
                 {
-                    attrs.push(name);
+                    if !attrs.iter().any(|a| a == name) {
+                        attrs.push(name.to_string());
+                    }
+                } else if md.module(&path).is_some() && !modules.contains(&path) {
+                    modules.push(path);
                 }
             }
-            attrs
+            (attrs, modules)
         }
     };
 
     let mut packages = Vec::new();
     for attr in &attrs {
-        if let Ok(info) = md.get(attr) {
-            packages.push(Package {
-                attr: PackageAttr::NixPkgs {
-                    attr: attr.to_string(),
-                },
-                version: if !info.version.is_empty() {
-                    Some(info.version)
-                } else {
-                    None
-                },
-                pname: Some(info.pname),
-                ..Default::default()
-            });
-        } else {
-            packages.push(Package {
-                attr: PackageAttr::NixPkgs {
-                    attr: attr.to_string(),
-                },
+        packages.push(package(
+            md,
+            attr,
+            PackageAttr::NixPkgs {
+                attr: attr.to_string(),
+            },
+        ));
+    }
+    for path in modules {
+        // Enabled modules come with details of the package they install
+        let pkg_attr = md.module(&path).and_then(|m| m.attribute);
+        let attr = PackageAttr::Module { path };
+        packages.push(match pkg_attr {
+            Some(pkg_attr) => package(md, &pkg_attr, attr),
+            None => Package {
+                attr,
                 ..Default::default()
-            });
-        }
+            },
+        });
     }
     Ok(packages)
 }
+
+/// A [`Package`] for `attr`, with the name and version of `pkg_attr` when known.
+fn package(md: &Metadata, pkg_attr: &str, attr: PackageAttr) -> Package {
+    match md.get(pkg_attr) {
+        Ok(info) => Package {
+            attr,
+            version: if !info.version.is_empty() {
+                Some(info.version)
+            } else {
+                None
+            },
+            pname: Some(info.pname),
+            ..Default::default()
+        },
+        Err(_) => Package {
+            attr,
+            ..Default::default()
+        },
+    }
+}
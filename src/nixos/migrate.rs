@@ -0,0 +1,177 @@
+use super::{AuthMethod, install::spawn_config_helper};
+use crate::{
+    ConfigEntry, Error, MigrationReport, Result,
+    config::configfile::{self, ConfigMode, LibSnowConfig},
+    dbus,
+    metadata::Metadata,
+    toml as tomlcfg,
+    utils::misc::{
+        dropped_options, enabled_programs, move_program_options, move_toml_program_options,
+        plan_migration,
+    },
+};
+use toml::Value as TomlValue;
+use tracing::debug;
+
+/// Rewrite renamed attributes in the system config against `md` (typically the
+/// database of the revision being updated to) and rebuild.
+/// Removed attributes are only reported, since they have no replacement.
+pub async fn migrate(md: &Metadata, auth_method: AuthMethod<'_>) -> Result<MigrationReport> {
+    let config = configfile::get_config()?;
+    let (content, output_path, report) = prepare_migrate(md, &config)?;
+    if report.renamed.is_empty() {
+        return Ok(report);
+    }
+
+    match auth_method {
+        AuthMethod::Dbus => dbus::config(&content, "switch").await?,
+        _ => {
+            let mut child =
+                spawn_config_helper(&content, &output_path, &config, auth_method).await?;
+            let status = child.wait().await?;
+            debug!("{}", status);
+            if !status.success() {
+                return Err(Error::SubprocessFailed {
+                    reason: "failed to rebuild".into(),
+                });
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Compute the migrated system config without writing it.
+pub fn prepare(md: &Metadata) -> Result<(String, MigrationReport)> {
+    let config = configfile::get_config()?;
+    let (content, _output_path, report) = prepare_migrate(md, &config)?;
+    Ok((content, report))
+}
+
+fn prepare_migrate(
+    md: &Metadata,
+    config: &LibSnowConfig,
+) -> Result<(String, String, MigrationReport)> {
+    match config.mode {
+        ConfigMode::Toml => {
+            let path = tomlcfg::system_config_file_path()?;
+            let mut pf = tomlcfg::read_system(std::path::Path::new(&path))?;
+            let programs: Vec<String> = pf
+                .options
+                .iter()
+                .filter(|(_, v)| **v == TomlValue::Boolean(true))
+                .filter_map(|(k, _)| k.strip_prefix("programs.")?.strip_suffix(".enable"))
+                .filter(|name| !name.contains('.'))
+                .map(str::to_string)
+                .collect();
+            let mut report =
+                plan_migration(&pf.packages, &programs, md, |a| md.has_program_option(a))?;
+
+            let mut removed = Vec::new();
+            for r in &report.renamed {
+                match r.from_entry {
+                    ConfigEntry::Package => pf.packages.retain(|p| p != &r.from),
+                    ConfigEntry::Program => {
+                        let to = (r.to_entry == ConfigEntry::Program).then_some(r.to.as_str());
+                        let dropped = move_toml_program_options(&mut pf.options, &r.from, to);
+                        removed.extend(dropped_options(r, dropped));
+                    }
+                }
+                match r.to_entry {
+                    ConfigEntry::Package => {
+                        if !pf.packages.contains(&r.to) {
+                            pf.packages.push(r.to.clone());
+                        }
+                    }
+                    ConfigEntry::Program if r.from_entry == ConfigEntry::Package => {
+                        pf.options.insert(
+                            format!("programs.{}.enable", r.to),
+                            TomlValue::Boolean(true),
+                        );
+                    }
+                    ConfigEntry::Program => {}
+                }
+            }
+            pf.packages.sort();
+            report.removed.extend(removed);
+            Ok((toml::to_string_pretty(&pf)?, path, report))
+        }
+        ConfigMode::Nix => {
+            let mut current = config.read_system_config_file()?;
+            let raw_packages = nix_editor::read::getarrvals(&current, "environment.systemPackages")
+                .unwrap_or_default();
+            let packages: Vec<String> = raw_packages
+                .iter()
+                .map(|x| x.strip_prefix("pkgs.").unwrap_or(x).to_string())
+                .collect();
+            let programs = enabled_programs(&current);
+            let mut report =
+                plan_migration(&packages, &programs, md, |a| md.has_program_option(a))?;
+
+            let mut rm_pkgs = vec![];
+            let mut add_pkgs = vec![];
+            let mut removed = Vec::new();
+            for r in &report.renamed {
+                match r.from_entry {
+                    ConfigEntry::Package => {
+                        if let Some(raw) = raw_packages
+                            .iter()
+                            .find(|x| x.strip_prefix("pkgs.").unwrap_or(x) == r.from)
+                        {
+                            rm_pkgs.push(raw.clone());
+                        }
+                    }
+                    ConfigEntry::Program => {
+                        let to = (r.to_entry == ConfigEntry::Program).then_some(r.to.as_str());
+                        let (migrated, dropped) = move_program_options(&current, &r.from, to)?;
+                        current = migrated;
+                        removed.extend(dropped_options(r, dropped));
+                    }
+                }
+                match r.to_entry {
+                    ConfigEntry::Package => {
+                        if !packages.contains(&r.to) && !add_pkgs.contains(&r.to) {
+                            add_pkgs.push(r.to.clone());
+                        }
+                    }
+                    ConfigEntry::Program if r.from_entry == ConfigEntry::Package => {
+                        let key = format!("programs.{}.enable", r.to);
+                        current =
+                            nix_editor::write::write(&current, &key, "true").map_err(|e| {
+                                Error::NixEditor {
+                                    reason: e.to_string(),
+                                }
+                            })?;
+                    }
+                    ConfigEntry::Program => {}
+                }
+            }
+            if !rm_pkgs.is_empty() {
+                current = nix_editor::write::rmarr(&current, "environment.systemPackages", rm_pkgs)
+                    .map_err(|e| Error::NixEditor {
+                        reason: e.to_string(),
+                    })?;
+            }
+            if !add_pkgs.is_empty() {
+                if let Ok(withvals) =
+                    nix_editor::read::getwithvalue(&current, "environment.systemPackages")
+                    && !withvals.contains(&String::from("pkgs"))
+                {
+                    add_pkgs = add_pkgs.iter().map(|x| format!("pkgs.{}", x)).collect();
+                }
+                current =
+                    nix_editor::write::addtoarr(&current, "environment.systemPackages", add_pkgs)
+                        .map_err(|e| Error::NixEditor {
+                        reason: e.to_string(),
+                    })?;
+            }
+            let path = config
+                .system_config_file
+                .clone()
+                .ok_or_else(|| Error::Config {
+                    reason: "failed to get system config path".into(),
+                })?;
+            report.removed.extend(removed);
+            Ok((current, path, report))
+        }
+    }
+}
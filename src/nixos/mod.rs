@@ -1,13 +1,62 @@
 pub mod batch;
 pub mod install;
 pub mod list;
+pub mod migrate;
 pub mod rebuild;
 pub mod remove;
 pub mod update;
 
+use crate::{NIXARCH, Result, metadata::Metadata};
+
 #[non_exhaustive]
 pub enum AuthMethod<'a> {
     Dbus,
     Sudo,
     Custom(&'a str),
 }
+
+/// The option set whose `enable` option installs `target`: a module path such
+/// as `services.tailscale`, or `programs.<attr>` for packages with a program
+/// module. `None` if `target` belongs in `environment.systemPackages`.
+fn enable_prefix(md: &Metadata, target: &str) -> Option<String> {
+    if md.module(target).is_some() {
+        Some(target.to_string())
+    } else if md.has_program_option(target) {
+        Some(format!("programs.{}", target))
+    } else {
+        None
+    }
+}
+
+/// The option set to clear when removing `target`. Falls back to
+/// `programs.<attr>` so that program modules enabled by hand are removed too.
+fn removal_prefix(md: &Metadata, target: &str) -> String {
+    enable_prefix(md, target).unwrap_or_else(|| format!("programs.{}", target))
+}
+
+/// Resolve an install target, which is either a module path or a package.
+/// Modules are only enabled when named by path; frontends can offer the
+/// modules of a package from [`Metadata::modules_for`].
+fn resolve_target(md: &Metadata, target: &str) -> Result<String> {
+    match md.module(target) {
+        Some(module) => Ok(module.path),
+        None => md.resolve_installable(target),
+    }
+}
+
+/// Check that `target` can be installed on this system. Modules are checked
+/// by the package they install, when known.
+fn ensure_target_available(md: &Metadata, target: &str) -> Result<()> {
+    match md.module(target) {
+        Some(module) => match module.attribute {
+            Some(attr) => md.ensure_available_on(&attr, &NIXARCH),
+            None => Ok(()),
+        },
+        None => md.ensure_available_on(target, &NIXARCH),
+    }
+}
+
+/// Whether `target` is a known module or package that can be removed.
+fn is_known_target(md: &Metadata, target: &str) -> bool {
+    md.module(target).is_some() || md.get(target).is_ok()
+}
@@ -4,7 +4,7 @@ use crate::{
     config::configfile::{self, ConfigMode, LibSnowConfig},
     dbus,
     metadata::Metadata,
-    nixos::list::list_systempackages,
+    nixos::{is_known_target, list::list_systempackages, removal_prefix},
     toml as tomlcfg,
 };
 use tokio::io::AsyncWriteExt;
@@ -46,7 +46,7 @@ fn prepare_remove(
 
     let mut pkgs_to_remove = vec![];
     for pkg in pkgs {
-        if md.get(pkg).is_ok() {
+        if is_known_target(md, pkg) {
             if installed.contains(&pkg.to_string()) {
                 pkgs_to_remove.push(pkg.to_string());
             } else {
@@ -67,7 +67,7 @@ fn prepare_remove(
             let mut pf = tomlcfg::read_system(std::path::Path::new(&path))?;
             for attr in &pkgs_to_remove {
                 pf.packages.retain(|p| p != attr);
-                let prefix = format!("programs.{}.", attr);
+                let prefix = format!("{}.", removal_prefix(md, attr));
                 let keys_to_remove: Vec<String> = pf
                     .options
                     .keys()
@@ -84,7 +84,7 @@ fn prepare_remove(
             let mut current = config.read_system_config_file()?;
             let mut arr_pkgs = vec![];
             for attr in &pkgs_to_remove {
-                let key = format!("programs.{}.enable", attr);
+                let key = format!("{}.enable", removal_prefix(md, attr));
                 if nix_editor::read::readvalue(&current, &key).is_ok() {
                     current =
                         nix_editor::write::deref(&current, &key).map_err(|e| Error::NixEditor {
@@ -1,7 +1,11 @@
 use super::AuthMethod;
 use crate::{
-    Error, HELPER_EXEC, PackageUpdate, Result, config::configfile::get_config, dbus,
-    metadata::Metadata, nixos::list::list_systempackages, utils,
+    Error, HELPER_EXEC, PackageUpdate, Result,
+    config::configfile::get_config,
+    dbus,
+    metadata::{Metadata, RevisionDiff},
+    nixos::list::list_systempackages,
+    utils,
 };
 use tracing::debug;
 
@@ -9,6 +13,12 @@ pub async fn updatable(md: &Metadata) -> Result<Vec<PackageUpdate>> {
     utils::misc::updatable(list_systempackages(md)?).await
 }
 
+/// Everything that changes between `md` and the latest nixpkgs revision.
+pub async fn preview(md: &Metadata) -> Result<RevisionDiff> {
+    let latest = Metadata::connect_latest().await?;
+    md.diff(&latest)
+}
+
 pub async fn update(auth_method: AuthMethod<'_>) -> Result<()> {
     match auth_method {
         AuthMethod::Dbus => update_dbus().await,
@@ -1,8 +1,12 @@
-use crate::{Error, NIX_BACKEND, NixBackend, PackageAttr, Result, profile::list::list};
+use crate::{
+    Error, NIX_BACKEND, NIXARCH, NixBackend, PackageAttr, Result, metadata::Metadata,
+    profile::list::list,
+};
 use tokio::process::Command;
+use tracing::warn;
 
-pub async fn install(pkgs: &[&str]) -> Result<()> {
-    let mut child = install_spawn(pkgs)?;
+pub async fn install(pkgs: &[&str], md: &Metadata) -> Result<()> {
+    let mut child = install_spawn(pkgs, md)?;
     let status = child.wait().await?;
     if !status.success() {
         Err(Error::SubprocessFailed {
@@ -13,15 +17,32 @@ pub async fn install(pkgs: &[&str]) -> Result<()> {
     }
 }
 
-pub fn install_spawn(pkgs: &[&str]) -> Result<tokio::process::Child> {
+pub fn install_spawn(pkgs: &[&str], md: &Metadata) -> Result<tokio::process::Child> {
     let installed = list()?;
     let mut pkgs_to_install = Vec::new();
     for pkg in pkgs {
+        // Flake references and attrs missing from the database are left to nix
+        let pkg = if pkg.contains('#') || pkg.contains(':') {
+            pkg.to_string()
+        } else {
+            match md.resolve_installable(pkg) {
+                Ok(attr) => {
+                    md.ensure_available_on(&attr, &NIXARCH)?;
+                    attr
+                }
+                Err(e @ Error::PackageNotFound { .. }) => {
+                    warn!("{}", e);
+                    pkg.to_string()
+                }
+                Err(e) => return Err(e),
+            }
+        };
         if installed.iter().any(|x| match x.attr {
-            PackageAttr::NixPkgs { ref attr } => attr == pkg,
+            PackageAttr::NixPkgs { ref attr } => *attr == pkg,
             PackageAttr::External { ref attr, ref url } => {
-                (format!("{}#{}", url, attr) == *pkg) || (attr.ends_with(".default") && url == pkg)
+                (format!("{}#{}", url, attr) == pkg) || (attr.ends_with(".default") && *url == pkg)
             }
+            PackageAttr::Module { .. } => false,
         }) {
             println!("Package {} is already installed", pkg);
         } else {
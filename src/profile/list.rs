@@ -95,9 +95,11 @@ pub fn name_from_attr(attr: &str) -> Result<String> {
                     });
                 }
             }
+            PackageAttr::Module { .. } => {}
         }
     }
     Err(Error::PackageNotFound {
         attr: attr.to_string(),
+        suggestions: Vec::new(),
     })
 }
@@ -24,6 +24,7 @@ pub fn remove_spawn(pkgs: &[&str]) -> Result<tokio::process::Child> {
         .map(|x| match x.attr {
             PackageAttr::NixPkgs { attr } => attr,
             PackageAttr::External { url, attr } => format!("{}#{}", url, attr),
+            PackageAttr::Module { path } => path,
         })
         .collect::<Vec<_>>();
     let mut pkgs_to_remove = Vec::new();
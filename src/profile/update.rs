@@ -69,6 +69,7 @@ pub async fn updatable_all() -> Result<Vec<PackageUpdate>> {
                     }
                 }
             }
+            PackageAttr::Module { .. } => {}
         }
     }
     Ok(updatable)
@@ -1,5 +1,7 @@
 use crate::{
-    Error, ICON_UPDATER_EXEC, Package, PackageAttr, PackageUpdate, Result, metadata::Metadata,
+    ConfigEntry, Error, ICON_UPDATER_EXEC, MigrationReport, Package, PackageAttr, PackageUpdate,
+    RemovedAttr, RenamedAttr, Result,
+    metadata::{Metadata, Resolution},
 };
 use tracing::debug;
 
@@ -51,6 +53,12 @@ pub async fn updatable_user(installed: Vec<Package>) -> Result<Vec<PackageUpdate
     compare_installed(&md, installed)
 }
 
+/// Like [`updatable`], comparing against the nix-channel `nix-env` uses.
+pub async fn updatable_channel(installed: Vec<Package>) -> Result<Vec<PackageUpdate>> {
+    let md = Metadata::connect_channel().await?;
+    compare_installed(&md, installed)
+}
+
 fn compare_installed(md: &Metadata, installed: Vec<Package>) -> Result<Vec<PackageUpdate>> {
     let mut updatable = vec![];
 
@@ -78,12 +86,181 @@ fn compare_installed(md: &Metadata, installed: Vec<Package>) -> Result<Vec<Packa
                     }
                 }
             }
-            PackageAttr::External { url: _, attr: _ } => {}
+            // Modules are updated along with the system
+            PackageAttr::External { url: _, attr: _ } | PackageAttr::Module { .. } => {}
         }
     }
     Ok(updatable)
 }
 
+/// Check config entries against the `aliases` table of `md`.
+/// Programs are only considered when `md` no longer has a module for them.
+pub(crate) fn plan_migration(
+    packages: &[String],
+    programs: &[String],
+    md: &Metadata,
+    has_program_option: impl Fn(&str) -> bool,
+) -> Result<MigrationReport> {
+    let mut report = MigrationReport::default();
+
+    let entries = packages.iter().map(|p| (p, ConfigEntry::Package)).chain(
+        programs
+            .iter()
+            .filter(|p| !has_program_option(p))
+            .map(|p| (p, ConfigEntry::Program)),
+    );
+
+    for (attr, entry) in entries {
+        match md.resolve(attr) {
+            Ok(Resolution::Renamed {
+                attribute, message, ..
+            }) => {
+                let to_entry = if entry == ConfigEntry::Program && has_program_option(&attribute) {
+                    ConfigEntry::Program
+                } else {
+                    ConfigEntry::Package
+                };
+                report.renamed.push(RenamedAttr {
+                    from: attr.clone(),
+                    from_entry: entry,
+                    to: attribute,
+                    to_entry,
+                    message,
+                });
+            }
+            Ok(Resolution::Removed { message, .. }) => report.removed.push(RemovedAttr {
+                attr: attr.clone(),
+                entry,
+                message,
+            }),
+            Ok(Resolution::Found { .. }) | Err(Error::PackageNotFound { .. }) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Every option set in the top-level attribute set of a Nix config, with
+/// nested attribute sets flattened to dotted paths, e.g. `programs.git.enable`.
+/// Options in `let` bindings, strings and comments are not included.
+pub(crate) fn config_options(content: &str) -> Vec<(String, String)> {
+    let Ok(options) = nix_editor::parse::get_collection(content.to_string()) else {
+        return Vec::new();
+    };
+    let mut options: Vec<(String, String)> = options
+        .into_iter()
+        .map(|(key, value)| {
+            let key = key.split('.').map(str::trim).collect::<Vec<_>>().join(".");
+            (key, value.trim().to_string())
+        })
+        .collect();
+    options.sort();
+    options
+}
+
+/// Paths of every `<path>.enable = true` set in a Nix config, e.g.
+/// `programs.git` or `services.tailscale`.
+pub(crate) fn enabled_options(content: &str) -> Vec<String> {
+    config_options(content)
+        .into_iter()
+        .filter(|(_, value)| value == "true")
+        .filter_map(|(key, _)| key.strip_suffix(".enable").map(str::to_string))
+        .collect()
+}
+
+/// Names of every top-level `programs.<name>.enable = true` set in a Nix config.
+pub(crate) fn enabled_programs(content: &str) -> Vec<String> {
+    enabled_options(content)
+        .into_iter()
+        .filter_map(|path| path.strip_prefix("programs.").map(str::to_string))
+        .filter(|name| !name.contains('.'))
+        .collect()
+}
+
+/// Move every `programs.<from>.*` option of a Nix config to `programs.<to>.*`.
+/// When `to` is `None` the options are removed, and those other than `enable`
+/// are returned so the user can be told about them.
+pub(crate) fn move_program_options(
+    content: &str,
+    from: &str,
+    to: Option<&str>,
+) -> Result<(String, Vec<String>)> {
+    let prefix = format!("programs.{}.", from);
+    let mut current = content.to_string();
+    let mut dropped = Vec::new();
+    for (key, value) in config_options(content) {
+        let Some(rest) = key.strip_prefix(&prefix) else {
+            continue;
+        };
+        match to {
+            Some(to) => {
+                let new_key = format!("programs.{}.{}", to, rest);
+                current = nix_editor::write::write(&current, &new_key, &value).map_err(|e| {
+                    Error::NixEditor {
+                        reason: e.to_string(),
+                    }
+                })?;
+            }
+            None if rest != "enable" => dropped.push(key.clone()),
+            None => {}
+        }
+        current = nix_editor::write::deref(&current, &key).map_err(|e| Error::NixEditor {
+            reason: e.to_string(),
+        })?;
+    }
+    // Drop the `programs.<from> = { };` left behind by a nested definition
+    if let Ok(emptied) = nix_editor::write::deref(&current, &format!("programs.{}", from)) {
+        current = emptied;
+    }
+    Ok((current, dropped))
+}
+
+/// Report options left behind by [`move_program_options`] for `renamed`.
+pub(crate) fn dropped_options(renamed: &RenamedAttr, options: Vec<String>) -> Vec<RemovedAttr> {
+    options
+        .into_iter()
+        .map(|option| RemovedAttr {
+            attr: option,
+            entry: ConfigEntry::Program,
+            message: format!(
+                "{} was replaced by the package {}, which has no program module",
+                renamed.from, renamed.to
+            ),
+        })
+        .collect()
+}
+
+/// Rename the `programs.<from>.*` options of a TOML packages file like
+/// [`move_program_options`].
+pub(crate) fn move_toml_program_options(
+    options: &mut std::collections::BTreeMap<String, ::toml::Value>,
+    from: &str,
+    to: Option<&str>,
+) -> Vec<String> {
+    let prefix = format!("programs.{}.", from);
+    let keys: Vec<String> = options
+        .keys()
+        .filter(|k| k.starts_with(&prefix))
+        .cloned()
+        .collect();
+    let mut dropped = Vec::new();
+    for key in keys {
+        let Some(value) = options.remove(&key) else {
+            continue;
+        };
+        let rest = &key[prefix.len()..];
+        match to {
+            Some(to) => {
+                options.insert(format!("programs.{}.{}", to, rest), value);
+            }
+            None if rest != "enable" => dropped.push(key),
+            None => {}
+        }
+    }
+    dropped
+}
+
 pub fn refresh_icons() -> Result<()> {
     let output = std::process::Command::new(ICON_UPDATER_EXEC).output()?;
     debug!("{}", String::from_utf8(output.stdout)?);